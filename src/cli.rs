@@ -0,0 +1,99 @@
+use std::{net::IpAddr, path::PathBuf};
+
+use clap::Parser;
+
+use log::LevelFilter;
+
+use rust_web_cgi::config::{CgiMountConfig, Config};
+
+/// A minimalistic CGI-capable HTTP server
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Configuration file (defaults to config.toml if it exists)
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Overrides a configuration setting (e.g. server.pool_size=8)
+    #[arg(short = 's', long = "set", value_name = "SECTION.KEY=VALUE")]
+    pub overrides: Vec<String>,
+
+    /// IP address to bind to (replaces the configured listeners)
+    #[arg(short, long, value_name = "ADDRESS")]
+    pub bind: Option<IpAddr>,
+
+    /// Port to listen on (replaces the configured listeners)
+    #[arg(short, long)]
+    pub port: Option<u16>,
+
+    /// Folder containing the static files
+    #[arg(short = 'r', long = "root", value_name = "DIR")]
+    pub document_root: Option<PathBuf>,
+
+    /// Folder containing the CGI programs
+    #[arg(long, value_name = "DIR")]
+    pub cgi_dir: Option<PathBuf>,
+
+    /// URL path prefix at which the CGI programs are served
+    #[arg(long, value_name = "PATH")]
+    pub cgi_path: Option<String>,
+
+    /// Minimum number of worker threads (the maximum is raised to match if
+    /// needed)
+    #[arg(short, long)]
+    pub workers: Option<usize>,
+
+    /// Log level (off, error, warn, info, debug or trace), overriding RUST_LOG
+    #[arg(short, long, value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+
+    /// Validates the configuration and exits
+    #[arg(long)]
+    pub check_config: bool,
+}
+
+impl Cli {
+    /// Applies the command-line flags on top of the loaded configuration. The
+    /// address flags replace the configured listeners with a single one,
+    /// based on the address of the first listener. The CGI flags change the
+    /// first CGI mount, which is created if the configuration has none.
+    ///
+    pub fn apply(&self, config: &mut Config) {
+        if self.bind.is_some() || self.port.is_some() {
+            let mut address = config.listeners()[0].address;
+            if let Some(bind) = self.bind {
+                address.set_ip(bind);
+            }
+            if let Some(port) = self.port {
+                address.set_port(port);
+            }
+
+            config.server.listen = address;
+            config.listener.clear();
+        }
+        if let Some(document_root) = &self.document_root {
+            config.static_files.root = document_root.clone();
+        }
+        if let Some(workers) = self.workers {
+            config.server.pool_size = workers;
+            config.server.max_pool_size = config.server.max_pool_size.max(workers);
+        }
+
+        if self.cgi_dir.is_some() || self.cgi_path.is_some() {
+            if config.cgi.is_empty() {
+                config.cgi.push(CgiMountConfig::new(
+                    String::from("cgi-bin"),
+                    PathBuf::from("cgi-bin"),
+                ));
+            }
+
+            let mount = &mut config.cgi[0];
+            if let Some(cgi_dir) = &self.cgi_dir {
+                mount.folder = cgi_dir.clone();
+            }
+            if let Some(cgi_path) = &self.cgi_path {
+                mount.path = cgi_path.clone();
+            }
+        }
+    }
+}
@@ -0,0 +1,1092 @@
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use http::{HeaderName, HeaderValue, Method, StatusCode};
+
+use serde::Deserialize;
+
+use crate::http_server::{
+    error_pages::ErrorPages,
+    middleware::BUILTIN_MIDDLEWARES,
+    rate_limit::RateLimit,
+    request::cgi_request::cgi_handler::{find_program, CgiOptions},
+    request::request::{
+        RequestLimits, DEFAULT_BODY_TIMEOUT, DEFAULT_HEADER_TIMEOUT, DEFAULT_MAX_REQUEST_SIZE,
+        DEFAULT_WRITE_TIMEOUT,
+    },
+    router::RoutePattern,
+    tls,
+    virtual_host::HostPattern,
+};
+
+/// Errors found while loading or validating the server configuration
+///
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(String),
+    InvalidOverride(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, error) => {
+                write!(f, "could not read {}: {error}", path.display())
+            }
+            ConfigError::Parse(message) => write!(f, "could not parse configuration: {message}"),
+            ConfigError::InvalidOverride(message) => write!(f, "invalid override: {message}"),
+            ConfigError::Invalid(message) => write!(f, "invalid configuration: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address and port the server listens on, unless `[[listener]]`
+    /// sections are configured
+    pub listen: SocketAddr,
+    /// Number of worker threads handling connections. The pool grows up to
+    /// `max_pool_size` threads when every thread is busy, and shrinks back
+    /// to `pool_size` threads once they are idle.
+    pub pool_size: usize,
+    /// Maximum number of worker threads
+    pub max_pool_size: usize,
+    /// Time after which idle worker threads above `pool_size` are stopped,
+    /// in seconds
+    pub worker_idle_timeout: u64,
+    /// Maximum number of accepted connections waiting for a worker thread.
+    /// Connections arriving while the queue is full are answered with a 503
+    /// Service Unavailable response.
+    pub queue_size: usize,
+    /// Maximum number of connections open at once, on every listener
+    pub max_connections: usize,
+    /// Maximum number of connections open at once from a single client IP
+    /// address
+    pub max_connections_per_ip: usize,
+    /// Time given to the requests being handled to complete when the server
+    /// is asked to shut down, in seconds
+    pub shutdown_timeout: u64,
+    /// Serves the connections with the event-driven backend instead of a
+    /// thread per connection. Requires the `event-loop` cargo feature.
+    pub event_loop: bool,
+    /// Middlewares applied to every request, in order
+    pub middleware: Vec<String>,
+    /// Shows the cause of CGI program failures in the error pages sent to
+    /// clients. Meant for development, since it exposes server paths.
+    pub cgi_debug: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            listen: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            pool_size: 4,
+            max_pool_size: 16,
+            worker_idle_timeout: 60,
+            queue_size: 64,
+            max_connections: 1024,
+            max_connections_per_ip: 64,
+            shutdown_timeout: 30,
+            event_loop: false,
+            middleware: BUILTIN_MIDDLEWARES
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            cgi_debug: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    /// Address and port to listen on. IPv6 addresses are written in brackets,
+    /// such as `[::]:8080`.
+    pub address: SocketAddr,
+    /// Whether an IPv6 listener only accepts IPv6 connections. By default,
+    /// IPv6 listeners are dual-stack and also accept IPv4 connections.
+    #[serde(default)]
+    pub ipv6_only: bool,
+    /// Names of the virtual hosts served by this listener. Every virtual host
+    /// is served if empty, and the default host is always served.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Makes the listener accept HTTPS connections
+    pub tls: Option<TlsConfig>,
+}
+
+impl ListenerConfig {
+    pub fn new(address: SocketAddr) -> ListenerConfig {
+        ListenerConfig {
+            address,
+            ipv6_only: false,
+            hosts: vec![],
+            tls: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnixListenerConfig {
+    /// Path of the Unix domain socket. A stale socket left at this path is
+    /// replaced.
+    pub path: PathBuf,
+    /// Permissions of the socket file (e.g. `0o660`), which control the
+    /// users allowed to connect
+    pub mode: Option<u32>,
+    /// Names of the virtual hosts served by this listener. Every virtual host
+    /// is served if empty, and the default host is always served.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM file containing the default certificate chain
+    pub cert: PathBuf,
+    /// PEM file containing the private key of the default certificate
+    pub key: PathBuf,
+    /// Certificates selected from the host name requested through SNI
+    #[serde(default)]
+    pub sni: Vec<SniCertificateConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SniCertificateConfig {
+    /// Host names the certificate is used for, possibly with wildcards
+    pub names: Vec<String>,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StaticConfig {
+    /// Folder containing the static files, served at the root of the domain
+    pub root: PathBuf,
+    /// Extensions of the files run as CGI programs instead of being served
+    pub cgi_extensions: Vec<String>,
+}
+
+impl Default for StaticConfig {
+    fn default() -> StaticConfig {
+        StaticConfig {
+            root: PathBuf::from("public_html"),
+            cgi_extensions: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CgiMountConfig {
+    /// URL path prefix at which the CGI programs are served
+    pub path: String,
+    /// Folder containing the CGI programs
+    pub folder: PathBuf,
+    /// Environment variables set for the programs
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Environment variables of the server passed on to the programs, e.g.
+    /// `PATH` or `TZ`
+    #[serde(default)]
+    pub pass_env: Vec<String>,
+    /// Time after which a program still running is killed, in seconds
+    pub timeout: Option<u64>,
+    /// Program running the CGI programs, e.g. `/usr/bin/python3`. It takes
+    /// precedence over the `cgi_interpreters` map.
+    pub interpreter: Option<PathBuf>,
+}
+
+impl CgiMountConfig {
+    pub fn new(path: String, folder: PathBuf) -> CgiMountConfig {
+        CgiMountConfig {
+            path,
+            folder,
+            env: HashMap::new(),
+            pass_env: vec![],
+            timeout: None,
+            interpreter: None,
+        }
+    }
+
+    /// Returns the settings applied to the programs of the mount, on top of
+    /// the settings of the whole server (see `Config::cgi_options`)
+    ///
+    pub fn options(&self, defaults: &CgiOptions) -> CgiOptions {
+        CgiOptions {
+            env: self.env.clone(),
+            pass_env: self.pass_env.clone(),
+            timeout: self.timeout.map(Duration::from_secs),
+            interpreter: self
+                .interpreter
+                .as_ref()
+                .map(|interpreter| find_program(interpreter).unwrap_or(interpreter.clone())),
+            ..defaults.clone()
+        }
+    }
+}
+
+/// A route serving static files or CGI programs. Exactly one of `exact`,
+/// `prefix` and `glob`, and exactly one of `static` and `cgi` must be set.
+/// CGI routes take the settings of CGI mounts.
+///
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    /// Single URL path served by the route
+    pub exact: Option<String>,
+    /// URL path prefix served by the route
+    pub prefix: Option<String>,
+    /// Glob matching the URL paths served by the route
+    pub glob: Option<String>,
+    /// Methods the route is restricted to, or every method if empty
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// Folder containing the static files served by the route
+    #[serde(rename = "static")]
+    pub static_folder: Option<PathBuf>,
+    /// Folder containing the CGI programs served by the route
+    pub cgi: Option<PathBuf>,
+    /// Environment variables set for the CGI programs
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Environment variables of the server passed on to the CGI programs
+    #[serde(default)]
+    pub pass_env: Vec<String>,
+    /// Time after which a CGI program still running is killed, in seconds
+    pub timeout: Option<u64>,
+    /// Program running the CGI programs
+    pub interpreter: Option<PathBuf>,
+}
+
+impl RouteConfig {
+    /// Returns the paths the route applies to
+    ///
+    pub fn pattern(&self) -> Option<RoutePattern> {
+        match (&self.exact, &self.prefix, &self.glob) {
+            (Some(exact), None, None) => Some(RoutePattern::Exact(exact.clone())),
+            (None, Some(prefix), None) => Some(RoutePattern::Prefix(prefix.clone())),
+            (None, None, Some(glob)) => Some(RoutePattern::Glob(glob.clone())),
+            _ => None,
+        }
+    }
+
+    /// Returns the CGI mount served by the route, at the literal part of its
+    /// pattern, if it serves CGI programs
+    ///
+    pub fn cgi_mount(&self) -> Option<CgiMountConfig> {
+        let path = self.pattern()?.base_path().to_string();
+        let folder = self.cgi.clone()?;
+
+        Some(CgiMountConfig {
+            env: self.env.clone(),
+            pass_env: self.pass_env.clone(),
+            timeout: self.timeout,
+            interpreter: self.interpreter.clone(),
+            ..CgiMountConfig::new(path, folder)
+        })
+    }
+
+    /// Tells whether any of the settings of CGI routes is set
+    ///
+    fn has_cgi_settings(&self) -> bool {
+        !self.env.is_empty()
+            || !self.pass_env.is_empty()
+            || self.timeout.is_some()
+            || self.interpreter.is_some()
+    }
+
+    /// Returns the methods the route is restricted to. Invalid methods are
+    /// skipped, since they are rejected by `validate_routes`.
+    ///
+    pub fn methods(&self) -> Vec<Method> {
+        self.methods
+            .iter()
+            .filter_map(|method| Method::from_str(method).ok())
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Maximum size of a request, in bytes
+    pub max_request_size: usize,
+    /// Time given to clients to send the headers of a request, in seconds
+    pub header_timeout: u64,
+    /// Time given to clients to send the body of a request once its headers
+    /// are received, in seconds
+    pub body_timeout: u64,
+    /// Time after which sending a response is given up if the client doesn't
+    /// receive any data, in seconds
+    pub write_timeout: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> LimitsConfig {
+        LimitsConfig {
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+            header_timeout: DEFAULT_HEADER_TIMEOUT.as_secs(),
+            body_timeout: DEFAULT_BODY_TIMEOUT.as_secs(),
+            write_timeout: DEFAULT_WRITE_TIMEOUT.as_secs(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// URL path prefix to which the limit applies. The limit applies to
+    /// every path if it is not set.
+    pub path: Option<String>,
+    /// Number of requests allowed per second and client IP address, on
+    /// average
+    pub rate: f64,
+    /// Number of requests a client can send at once
+    pub burst: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualHostConfig {
+    /// Host names served by the virtual host. Wildcards such as
+    /// `*.example.com` match any subdomain.
+    pub names: Vec<String>,
+    /// Folder containing the static files of the virtual host
+    pub root: PathBuf,
+    /// Extensions of the files of `root` run as CGI programs
+    #[serde(default)]
+    pub cgi_extensions: Vec<String>,
+    #[serde(default)]
+    pub cgi: Vec<CgiMountConfig>,
+    #[serde(default)]
+    pub route: Vec<RouteConfig>,
+    #[serde(default)]
+    pub error_pages: HashMap<String, String>,
+}
+
+impl VirtualHostConfig {
+    pub fn error_pages(&self) -> ErrorPages {
+        build_error_pages(&self.error_pages)
+    }
+}
+
+/// The server configuration, usually read from a TOML file. Every section is
+/// optional, and missing values fall back to the defaults.
+///
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub listener: Vec<ListenerConfig>,
+    pub unix_listener: Vec<UnixListenerConfig>,
+    #[serde(rename = "static")]
+    pub static_files: StaticConfig,
+    pub cgi: Vec<CgiMountConfig>,
+    /// Maps file extensions to the programs running the CGI programs with
+    /// these extensions, on every site
+    pub cgi_interpreters: HashMap<String, PathBuf>,
+    /// Additional routes of the default host, taking precedence over the
+    /// static folder and CGI mounts according to their patterns
+    pub route: Vec<RouteConfig>,
+    pub limits: LimitsConfig,
+    /// Request rate limits, applied to every listener and virtual host
+    pub rate_limit: Vec<RateLimitConfig>,
+    /// Headers added to every response
+    pub response_headers: HashMap<String, String>,
+    /// Maps status codes to the local URL paths of error documents
+    pub error_pages: HashMap<String, String>,
+    /// Name-based virtual hosts. The top-level static, CGI and error page
+    /// settings describe the default host, which serves requests for any
+    /// other host name.
+    pub vhost: Vec<VirtualHostConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            server: ServerConfig::default(),
+            listener: vec![],
+            unix_listener: vec![],
+            static_files: StaticConfig::default(),
+            cgi: vec![CgiMountConfig::new(
+                String::from("cgi-bin"),
+                PathBuf::from("cgi-bin"),
+            )],
+            cgi_interpreters: HashMap::new(),
+            route: vec![],
+            limits: LimitsConfig::default(),
+            rate_limit: vec![],
+            response_headers: HashMap::new(),
+            error_pages: HashMap::from([(String::from("404"), String::from("/404.html"))]),
+            vhost: vec![],
+        }
+    }
+}
+
+/// Parses the value of a command-line override. Valid TOML values (numbers,
+/// booleans, quoted strings, arrays...) are used as they are, and anything
+/// else is taken as a plain string.
+///
+fn parse_override_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+/// Applies a `section.key=value` override to a parsed configuration table
+///
+fn apply_override(table: &mut toml::Table, key_value: &str) -> Result<(), ConfigError> {
+    let (key, value) = key_value
+        .split_once('=')
+        .ok_or_else(|| ConfigError::InvalidOverride(format!("{key_value} (expected key=value)")))?;
+
+    let mut keys: Vec<&str> = key.trim().split('.').collect();
+    let last_key = keys.pop().unwrap_or("");
+    if last_key.is_empty() || keys.iter().any(|key| key.is_empty()) {
+        return Err(ConfigError::InvalidOverride(format!("invalid key {key}")));
+    }
+
+    let mut current = table;
+    for section in keys {
+        let entry = current
+            .entry(section)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        current = match entry {
+            toml::Value::Table(section_table) => section_table,
+            _ => {
+                return Err(ConfigError::InvalidOverride(format!(
+                    "{section} is not a section"
+                )))
+            }
+        };
+    }
+
+    current.insert(last_key.to_string(), parse_override_value(value.trim()));
+    Ok(())
+}
+
+impl Config {
+    /// Parses a configuration from TOML text, applying the given
+    /// `section.key=value` overrides on top of it.
+    ///
+    pub fn parse(contents: &str, overrides: &[String]) -> Result<Config, ConfigError> {
+        let mut table = toml::Table::from_str(contents)
+            .map_err(|error| ConfigError::Parse(error.to_string()))?;
+
+        for key_value in overrides {
+            apply_override(&mut table, key_value)?;
+        }
+
+        Config::deserialize(table).map_err(|error| ConfigError::Parse(error.to_string()))
+    }
+
+    /// Loads the configuration file at `path`, applying the given overrides.
+    /// If no path is given, the overrides are applied to the default
+    /// configuration.
+    ///
+    pub fn load(path: Option<&Path>, overrides: &[String]) -> Result<Config, ConfigError> {
+        let contents = match path {
+            None => String::from(""),
+            Some(path) => fs::read_to_string(path)
+                .map_err(|error| ConfigError::Read(path.to_path_buf(), error))?,
+        };
+
+        Config::parse(&contents, overrides)
+    }
+
+    /// Checks that the configuration values are usable, so that problems are
+    /// reported at startup instead of when handling requests.
+    ///
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.server.pool_size == 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "server.pool_size must be greater than zero",
+            )));
+        }
+
+        if self.server.max_pool_size < self.server.pool_size {
+            return Err(ConfigError::Invalid(String::from(
+                "server.max_pool_size must not be lower than server.pool_size",
+            )));
+        }
+
+        if self.server.event_loop && !cfg!(feature = "event-loop") {
+            return Err(ConfigError::Invalid(String::from(
+                "server.event_loop requires building with the event-loop feature",
+            )));
+        }
+
+        if self.server.queue_size == 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "server.queue_size must be greater than zero",
+            )));
+        }
+
+        if self.server.max_connections == 0 || self.server.max_connections_per_ip == 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "server.max_connections and server.max_connections_per_ip must be greater than zero",
+            )));
+        }
+
+        if self.limits.max_request_size == 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "limits.max_request_size must be greater than zero",
+            )));
+        }
+
+        for (name, timeout) in [
+            ("header_timeout", self.limits.header_timeout),
+            ("body_timeout", self.limits.body_timeout),
+            ("write_timeout", self.limits.write_timeout),
+        ] {
+            if timeout == 0 {
+                return Err(ConfigError::Invalid(format!(
+                    "limits.{name} must be greater than zero"
+                )));
+            }
+        }
+
+        let mut middlewares = vec![];
+        for name in &self.server.middleware {
+            if !BUILTIN_MIDDLEWARES.contains(&name.as_str()) {
+                return Err(ConfigError::Invalid(format!(
+                    "server.middleware: unknown middleware {name}"
+                )));
+            }
+            if middlewares.contains(&name) {
+                return Err(ConfigError::Invalid(format!(
+                    "server.middleware: {name} is listed more than once"
+                )));
+            }
+            middlewares.push(name);
+        }
+
+        for (name, value) in &self.response_headers {
+            if HeaderName::from_str(name).is_err() || HeaderValue::from_str(value).is_err() {
+                return Err(ConfigError::Invalid(format!(
+                    "response_headers: invalid header {name}"
+                )));
+            }
+        }
+
+        for rate_limit in &self.rate_limit {
+            if !rate_limit.rate.is_finite() || rate_limit.rate <= 0.0 || rate_limit.burst == 0 {
+                return Err(ConfigError::Invalid(String::from(
+                    "rate_limit: rate and burst must be positive numbers",
+                )));
+            }
+            if rate_limit
+                .path
+                .as_ref()
+                .is_some_and(|path| !path.starts_with('/'))
+            {
+                return Err(ConfigError::Invalid(String::from(
+                    "rate_limit: path must start with a slash",
+                )));
+            }
+        }
+
+        validate_folder("static.root", &self.static_files.root)?;
+        validate_cgi_extensions("static", &self.static_files.cgi_extensions)?;
+        validate_cgi_mounts("cgi", &self.cgi)?;
+        validate_cgi_extensions(
+            "cgi_interpreters",
+            &self.cgi_interpreters.keys().cloned().collect::<Vec<_>>(),
+        )?;
+        for (extension, interpreter) in &self.cgi_interpreters {
+            if find_program(interpreter).is_none() {
+                return Err(ConfigError::Invalid(format!(
+                    "cgi_interpreters: interpreter {} for {extension} was not found",
+                    interpreter.display()
+                )));
+            }
+        }
+        validate_routes("route", &self.route)?;
+        validate_error_pages("error_pages", &self.error_pages)?;
+
+        let mut host_names = vec![];
+        for virtual_host in &self.vhost {
+            if virtual_host.names.is_empty() {
+                return Err(ConfigError::Invalid(String::from(
+                    "vhost.names must list at least one host name",
+                )));
+            }
+
+            for name in &virtual_host.names {
+                let pattern = HostPattern::parse(name)
+                    .map_err(|message| ConfigError::Invalid(format!("vhost.names: {message}")))?;
+                if host_names.contains(&pattern) {
+                    return Err(ConfigError::Invalid(format!(
+                        "vhost name {name} is used more than once"
+                    )));
+                }
+                host_names.push(pattern);
+            }
+
+            let section = format!("vhost {}", virtual_host.names[0]);
+            validate_folder(&format!("{section} root"), &virtual_host.root)?;
+            validate_cgi_extensions(&section, &virtual_host.cgi_extensions)?;
+            validate_cgi_mounts(&section, &virtual_host.cgi)?;
+            validate_routes(&section, &virtual_host.route)?;
+            validate_error_pages(&section, &virtual_host.error_pages)?;
+        }
+
+        let mut addresses = vec![];
+        for listener in self.listeners() {
+            if addresses.contains(&listener.address) {
+                return Err(ConfigError::Invalid(format!(
+                    "listener address {} is used more than once",
+                    listener.address
+                )));
+            }
+            addresses.push(listener.address);
+
+            if listener.ipv6_only && !listener.address.is_ipv6() {
+                return Err(ConfigError::Invalid(format!(
+                    "listener {}: ipv6_only requires an IPv6 address",
+                    listener.address
+                )));
+            }
+
+            if let Some(tls_config) = &listener.tls {
+                tls::load_server_config(tls_config).map_err(|error| {
+                    ConfigError::Invalid(format!("listener {}: {error}", listener.address))
+                })?;
+            }
+
+            self.validate_listener_hosts(&listener.address.to_string(), &listener.hosts)?;
+        }
+
+        let mut paths = vec![];
+        for listener in &self.unix_listener {
+            let name = listener.path.display().to_string();
+            if paths.contains(&&listener.path) {
+                return Err(ConfigError::Invalid(format!(
+                    "unix listener path {name} is used more than once"
+                )));
+            }
+            paths.push(&listener.path);
+
+            if listener.mode.is_some_and(|mode| mode > 0o777) {
+                return Err(ConfigError::Invalid(format!(
+                    "unix listener {name}: mode must be a permission value such as 0o660"
+                )));
+            }
+
+            let parent_exists = listener
+                .path
+                .parent()
+                .is_some_and(|parent| parent.as_os_str().is_empty() || parent.is_dir());
+            if !parent_exists {
+                return Err(ConfigError::Invalid(format!(
+                    "unix listener {name}: parent folder does not exist"
+                )));
+            }
+
+            self.validate_listener_hosts(&name, &listener.hosts)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the virtual hosts a listener is restricted to exist
+    ///
+    fn validate_listener_hosts(&self, listener: &str, hosts: &[String]) -> Result<(), ConfigError> {
+        for host in hosts {
+            let is_known = self
+                .vhost
+                .iter()
+                .any(|virtual_host| virtual_host.names.contains(host));
+            if !is_known {
+                return Err(ConfigError::Invalid(format!(
+                    "listener {listener}: unknown virtual host {host}"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the listeners the server should open. Without `[[listener]]`
+    /// sections, a single listener is opened on `server.listen`.
+    ///
+    pub fn listeners(&self) -> Vec<ListenerConfig> {
+        if self.listener.is_empty() {
+            vec![ListenerConfig::new(self.server.listen)]
+        } else {
+            self.listener.clone()
+        }
+    }
+
+    /// Returns the virtual hosts served by a listener restricted to the given
+    /// host names
+    ///
+    pub fn listener_virtual_hosts(&self, hosts: &[String]) -> Vec<&VirtualHostConfig> {
+        self.vhost
+            .iter()
+            .filter(|virtual_host| {
+                hosts.is_empty() || virtual_host.names.iter().any(|name| hosts.contains(name))
+            })
+            .collect()
+    }
+
+    /// Returns the error documents of the default host
+    ///
+    pub fn error_pages(&self) -> ErrorPages {
+        build_error_pages(&self.error_pages)
+    }
+
+    pub fn request_limits(&self) -> RequestLimits {
+        RequestLimits {
+            max_request_size: self.limits.max_request_size,
+            header_timeout: Duration::from_secs(self.limits.header_timeout),
+            body_timeout: Duration::from_secs(self.limits.body_timeout),
+            write_timeout: Duration::from_secs(self.limits.write_timeout),
+        }
+    }
+
+    /// Returns the settings applied to the CGI programs of every site: the
+    /// interpreters by extension, without the leading dot and with the
+    /// interpreters found in the `PATH` resolved to their full path, and the
+    /// debug mode
+    ///
+    pub fn cgi_options(&self) -> CgiOptions {
+        let interpreters = self
+            .cgi_interpreters
+            .iter()
+            .map(|(extension, interpreter)| {
+                (
+                    extension.trim_start_matches('.').to_string(),
+                    find_program(interpreter).unwrap_or(interpreter.clone()),
+                )
+            })
+            .collect();
+
+        CgiOptions {
+            interpreters,
+            debug: self.server.cgi_debug,
+            ..CgiOptions::default()
+        }
+    }
+
+    /// Returns the configured request rate limits
+    ///
+    pub fn rate_limits(&self) -> Vec<RateLimit> {
+        self.rate_limit
+            .iter()
+            .map(|rate_limit| RateLimit {
+                path: rate_limit.path.clone(),
+                rate: rate_limit.rate,
+                burst: rate_limit.burst,
+            })
+            .collect()
+    }
+
+    /// Returns the time after which idle worker threads are stopped
+    ///
+    pub fn worker_idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.server.worker_idle_timeout)
+    }
+
+    /// Returns the time given to the requests being handled to complete
+    /// when shutting down
+    ///
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.server.shutdown_timeout)
+    }
+}
+
+/// Converts configured error documents into an `ErrorPages` map. Entries
+/// with an invalid status code are skipped, since they are rejected by
+/// `validate_error_pages`.
+///
+fn build_error_pages(documents: &HashMap<String, String>) -> ErrorPages {
+    let mut error_pages = ErrorPages::new();
+    for (status, location) in documents {
+        if let Ok(status) = StatusCode::from_str(status) {
+            error_pages.insert(status, location.clone());
+        }
+    }
+
+    error_pages
+}
+
+/// Checks that the CGI mounts of a site have distinct, non-root URL paths
+/// and existing folders
+///
+fn validate_cgi_mounts(section: &str, mounts: &[CgiMountConfig]) -> Result<(), ConfigError> {
+    let mut cgi_paths = vec![];
+    for mount in mounts {
+        let path = mount.path.trim_matches('/');
+        if path.is_empty() {
+            return Err(ConfigError::Invalid(format!(
+                "{section}: cgi path can't be empty or the root path"
+            )));
+        }
+        if cgi_paths.contains(&path) {
+            return Err(ConfigError::Invalid(format!(
+                "{section}: cgi path {path} is mounted more than once"
+            )));
+        }
+        cgi_paths.push(path);
+
+        validate_folder(&format!("{section}: cgi folder"), &mount.folder)?;
+        validate_cgi_settings(&format!("{section}: cgi path {path}"), mount)?;
+    }
+
+    Ok(())
+}
+
+/// Checks the environment, timeout and interpreter of a CGI mount
+///
+fn validate_cgi_settings(context: &str, mount: &CgiMountConfig) -> Result<(), ConfigError> {
+    let mut names = mount.pass_env.iter().chain(mount.env.keys());
+    if let Some(name) = names.find(|name| !is_valid_env_name(name)) {
+        return Err(ConfigError::Invalid(format!(
+            "{context}: invalid environment variable name {name:?}"
+        )));
+    }
+    if mount.timeout == Some(0) {
+        return Err(ConfigError::Invalid(format!(
+            "{context}: timeout must be greater than zero"
+        )));
+    }
+    if let Some(interpreter) = &mount.interpreter {
+        if find_program(interpreter).is_none() {
+            return Err(ConfigError::Invalid(format!(
+                "{context}: interpreter {} was not found",
+                interpreter.display()
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Tells whether the given name can be used for an environment variable
+///
+fn is_valid_env_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(['=', '\0'])
+}
+
+/// Checks that the extensions of the files run as CGI programs are plain
+/// file extensions
+///
+fn validate_cgi_extensions(section: &str, extensions: &[String]) -> Result<(), ConfigError> {
+    for extension in extensions {
+        let extension = extension.trim_start_matches('.');
+        if extension.is_empty() || extension.contains(['.', '/']) {
+            return Err(ConfigError::Invalid(format!(
+                "{section}: invalid cgi extension {extension:?}"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the routes of a site have a single valid pattern, valid
+/// methods and a single existing folder
+///
+fn validate_routes(section: &str, routes: &[RouteConfig]) -> Result<(), ConfigError> {
+    for route in routes {
+        let path = match route.pattern() {
+            None => {
+                return Err(ConfigError::Invalid(format!(
+                    "{section}: a route needs exactly one of exact, prefix and glob"
+                )))
+            }
+            Some(
+                RoutePattern::Exact(path) | RoutePattern::Prefix(path) | RoutePattern::Glob(path),
+            ) => path,
+        };
+        if !path.starts_with('/') {
+            return Err(ConfigError::Invalid(format!(
+                "{section}: route path {path} must start with a slash"
+            )));
+        }
+
+        for method in &route.methods {
+            if Method::from_str(method).is_err() {
+                return Err(ConfigError::Invalid(format!(
+                    "{section}: route {path} has an invalid method {method}"
+                )));
+            }
+        }
+
+        match (&route.static_folder, &route.cgi) {
+            (Some(folder), None) => {
+                validate_folder(&format!("{section}: route {path} static folder"), folder)?;
+                if route.has_cgi_settings() {
+                    return Err(ConfigError::Invalid(format!(
+                        "{section}: route {path} serves static files, which take no cgi settings"
+                    )));
+                }
+            }
+            (None, Some(folder)) => {
+                validate_folder(&format!("{section}: route {path} cgi folder"), folder)?;
+                if let Some(mount) = route.cgi_mount() {
+                    validate_cgi_settings(&format!("{section}: route {path}"), &mount)?;
+                }
+            }
+            _ => {
+                return Err(ConfigError::Invalid(format!(
+                    "{section}: route {path} needs exactly one of static and cgi"
+                )))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that error documents are configured for error status codes and
+/// point to local paths
+///
+fn validate_error_pages(
+    section: &str,
+    documents: &HashMap<String, String>,
+) -> Result<(), ConfigError> {
+    for (status, location) in documents {
+        let valid_status = StatusCode::from_str(status)
+            .is_ok_and(|status| status.is_client_error() || status.is_server_error());
+        if !valid_status {
+            return Err(ConfigError::Invalid(format!(
+                "{section}: {status} is not an error status code"
+            )));
+        }
+        if !location.starts_with('/') {
+            return Err(ConfigError::Invalid(format!(
+                "{section}: error page {location} must be a local path starting with /"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the configured `path` is an existing folder
+///
+fn validate_folder(name: &str, path: &Path) -> Result<(), ConfigError> {
+    if path.is_dir() {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid(format!(
+            "{name} {} is not an existing folder",
+            path.display()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_values_use_defaults() {
+        let config = Config::parse("[server]\npool_size = 8\n", &[]).unwrap();
+
+        assert_eq!(config.server.pool_size, 8);
+        assert_eq!(config.server.listen, ServerConfig::default().listen);
+        assert_eq!(config.static_files.root, PathBuf::from("public_html"));
+    }
+
+    #[test]
+    fn overrides_replace_file_values() {
+        let overrides = vec![
+            String::from("server.listen=0.0.0.0:9000"),
+            String::from("static.root=www"),
+            String::from("limits.max_request_size=1024"),
+        ];
+        let config = Config::parse("[server]\nlisten = \"127.0.0.1:80\"\n", &overrides).unwrap();
+
+        assert_eq!(config.server.listen, SocketAddr::from(([0, 0, 0, 0], 9000)));
+        assert_eq!(config.static_files.root, PathBuf::from("www"));
+        assert_eq!(config.limits.max_request_size, 1024);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let result = Config::parse("[server]\nthreads = 4\n", &[]);
+
+        assert!(matches!(result, Err(ConfigError::Parse(_))));
+    }
+
+    #[test]
+    fn invalid_error_page_status_is_rejected() {
+        let mut config = Config::default();
+        config
+            .error_pages
+            .insert(String::from("200"), String::from("/ok.html"));
+
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn invalid_cgi_environment_names_are_rejected() {
+        let config = Config::parse(
+            "[[cgi]]\npath = \"cgi-bin\"\nfolder = \"cgi-bin\"\npass_env = [\"PATH\", \"TZ\"]\n",
+            &[],
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+
+        let mut config = config;
+        config.cgi[0].pass_env.push(String::from("LANG=C"));
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn cgi_routes_take_mount_settings() {
+        let config = Config::parse(
+            "[[route]]\nglob = \"/scripts/**/*.sh\"\ncgi = \"cgi-bin\"\ntimeout = 5\n\
+            interpreter = \"sh\"\nenv = { APP_ENV = \"test\" }\npass_env = [\"PATH\"]\n",
+            &[],
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+
+        let options = config.route[0]
+            .cgi_mount()
+            .unwrap()
+            .options(&config.cgi_options());
+        assert_eq!(options.env["APP_ENV"], "test");
+        assert_eq!(options.pass_env, vec![String::from("PATH")]);
+        assert_eq!(options.timeout, Some(Duration::from_secs(5)));
+        assert!(options.interpreter.unwrap().ends_with("sh"));
+
+        let mut config = config;
+        config.route[0].timeout = Some(0);
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+        config.route[0].timeout = None;
+        config.route[0].pass_env.push(String::from("LANG=C"));
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+
+        let static_route = Config::parse(
+            "[[route]]\nprefix = \"/docs\"\nstatic = \"public_html\"\ntimeout = 5\n",
+            &[],
+        )
+        .unwrap();
+        assert!(matches!(
+            static_route.validate(),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+}
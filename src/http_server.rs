@@ -1,3 +1,14 @@
 pub mod connection;
+pub mod connection_limits;
+pub mod error_pages;
+#[cfg(feature = "event-loop")]
+pub mod event_loop;
+pub mod listener;
+pub mod middleware;
+pub mod rate_limit;
 pub mod request;
 pub mod response;
+pub mod router;
+pub mod tls;
+pub mod transport;
+pub mod virtual_host;
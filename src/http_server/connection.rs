@@ -1,42 +1,68 @@
-use std::{io::prelude::*, net::TcpStream};
-
 use http::{Request, Response, StatusCode};
 
-use log::{debug, info};
+use log::{debug, info, warn};
 
 use crate::http_server::{
-    request::request::{load_request, RequestHandler},
-    response::generate_error_response,
-    response::response_to_string,
+    middleware::MiddlewareChain,
+    request::request::{load_request, parse_request, RequestLimits},
+    response::{generate_error_response, response_to_string},
+    transport::{ConnectionInfo, Transport},
+    virtual_host::{request_host, VirtualHosts},
 };
 
-type RequestHandlerList = Vec<Box<dyn RequestHandler<String> + Sync + Send>>;
-
 pub struct ConnectionHandler {
-    request_handlers: RequestHandlerList,
+    virtual_hosts: VirtualHosts,
+    limits: RequestLimits,
+    middlewares: MiddlewareChain,
 }
 
 impl ConnectionHandler {
-    pub fn new(request_handlers: RequestHandlerList) -> ConnectionHandler {
-        ConnectionHandler { request_handlers }
+    pub fn new(
+        virtual_hosts: VirtualHosts,
+        limits: RequestLimits,
+        middlewares: MiddlewareChain,
+    ) -> ConnectionHandler {
+        ConnectionHandler {
+            virtual_hosts,
+            limits,
+            middlewares,
+        }
     }
 
     /// Handles a single incoming HTTP request using a suitable handler.
     ///
-    /// Receives the request information as well as the TCP stream from which
-    /// the request was read. Handlers supplied to the ConnectionHandler are
-    /// tried in order, and the first `Some` response available is returned.
-    pub fn handle_request(&self, request: Request<String>, stream: &TcpStream) -> Response<String> {
-        let mut response = None;
-        for handler in &self.request_handlers {
-            response = response.or(handler.handle_request(stream, &request));
-            if let Some(_) = response {
+    /// Receives the request information as well as the details of the
+    /// connection from which the request was read. The request goes through
+    /// the middlewares, and is then dispatched to the virtual host matching
+    /// its host name. Error responses are replaced by the error documents
+    /// configured for that virtual host, and the response goes back through
+    /// the middlewares in the reverse order.
+    pub fn handle_request(
+        &self,
+        mut request: Request<String>,
+        connection: &ConnectionInfo,
+    ) -> Response<String> {
+        let mut early_response = None;
+        let mut middlewares_run = 0;
+        for middleware in &self.middlewares {
+            middlewares_run += 1;
+            early_response = middleware.before(connection, &mut request);
+            if early_response.is_some() {
                 break;
             }
         }
 
-        let mut response =
-            response.unwrap_or(generate_error_response(StatusCode::INTERNAL_SERVER_ERROR));
+        let host = request_host(&request);
+        debug!("Requested host: {:?}", host);
+        let virtual_host = self.virtual_hosts.select(host.as_deref());
+
+        let response =
+            early_response.unwrap_or_else(|| virtual_host.dispatch(&request, connection));
+        let mut response = virtual_host.apply_error_page(response, connection);
+
+        for middleware in self.middlewares[..middlewares_run].iter().rev() {
+            middleware.after(connection, &request, &mut response);
+        }
 
         if request.method() == "HEAD" {
             *response.body_mut() = String::from("");
@@ -45,33 +71,203 @@ impl ConnectionHandler {
         response
     }
 
-    /// Reads an HTTP request from a TCP stream and writes the corresponding
-    /// response back to it.
-    ///
-    /// # Panics
+    /// Returns the limits applied while reading requests
     ///
-    /// The `handle_connection` method panics if the stream can't be written to
-    /// or flushed (for instance if it was closed).
+    pub fn limits(&self) -> &RequestLimits {
+        &self.limits
+    }
+
+    /// Builds the text of the response to a request which was read from a
+    /// client, or to the error found while reading it.
     ///
-    pub fn handle_connection(&self, mut stream: TcpStream) {
-        info!("New request received");
-        let request = load_request(&mut stream);
+    pub fn respond(
+        &self,
+        request: Result<Request<String>, StatusCode>,
+        connection: &ConnectionInfo,
+    ) -> String {
         debug!("{:?}", request);
 
         let response = match request {
-            Ok(request) => self.handle_request(request, &stream),
-            Err(status) => generate_error_response(status),
+            Ok(request) => self.handle_request(request, connection),
+            Err(status) => self
+                .virtual_hosts
+                .default_host()
+                .apply_error_page(generate_error_response(status), connection),
         };
 
         let response_text = response_to_string(response);
-        info!("Writing response");
         debug!("Response: \n{response_text}\n");
 
-        stream
-            .write_all(response_text.as_bytes())
-            .expect("Error writing response to the TCP Stream");
-        stream.flush().expect("Error flushing TCP stream");
+        response_text
+    }
+
+    /// Parses the request data received from a client and builds the text of
+    /// the response. Used when the request was read by the caller, without
+    /// going through a transport.
+    ///
+    pub fn handle_request_data(&self, data: Vec<u8>, connection: &ConnectionInfo) -> String {
+        info!("New request received");
+        self.respond(parse_request(data, &self.limits), connection)
+    }
+
+    /// Reads an HTTP request from a transport (such as a TCP or TLS stream)
+    /// and writes the corresponding response back to it. Clients which are
+    /// too slow to send their request get a REQUEST TIMEOUT response, and
+    /// sending the response is given up if the client stops receiving data.
+    ///
+    pub fn handle_connection<T: Transport>(&self, mut stream: T) {
+        info!("New request received");
+        let connection = stream.connection_info();
+        let request = load_request(&mut stream, &self.limits);
+
+        let response_text = self.respond(request, &connection);
+        info!("Writing response");
+
+        let result = stream
+            .set_write_timeout(Some(self.limits.write_timeout))
+            .and_then(|_| stream.write_all(response_text.as_bytes()))
+            .and_then(|_| stream.flush());
+        match result {
+            Err(error) => warn!("Error writing the response: {error}"),
+            Ok(_) => info!("Finished writing response"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use http::HeaderValue;
+
+    use crate::http_server::{
+        error_pages::ErrorPages,
+        middleware::{Middleware, ResponseHeaders},
+        request::{
+            cgi_request::cgi_handler::{CgiOptions, CgiRequestHandler},
+            static_request::static_handler::StaticRequestHandler,
+        },
+        router::{RoutePattern, Router},
+        transport::{MemoryTransport, TlsInfo},
+        virtual_host::VirtualHost,
+    };
+
+    fn sample_handler() -> ConnectionHandler {
+        sample_handler_with(vec![])
+    }
+
+    fn sample_handler_with(middlewares: MiddlewareChain) -> ConnectionHandler {
+        let mut router = Router::new();
+        router.add(
+            RoutePattern::Prefix(String::from("/cgi-bin")),
+            vec![],
+            Box::new(CgiRequestHandler::new(
+                String::from("cgi-bin"),
+                String::from("cgi-bin"),
+                StaticRequestHandler::new(String::new(), String::from("public_html")),
+                CgiOptions::default(),
+            )),
+        );
+        router.add(
+            RoutePattern::Prefix(String::from("/")),
+            vec![],
+            Box::new(StaticRequestHandler::new(
+                String::new(),
+                String::from("public_html"),
+            )),
+        );
+        let default_host = VirtualHost::new(router, ErrorPages::new());
+
+        ConnectionHandler::new(
+            VirtualHosts::new(default_host),
+            RequestLimits::default(),
+            middlewares,
+        )
+    }
+
+    fn send_request(request: &str, connection: ConnectionInfo) -> String {
+        let mut transport = MemoryTransport::new(request.as_bytes(), connection);
+        sample_handler().handle_connection(&mut transport);
+
+        String::from_utf8(transport.output().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn static_file_is_served_over_any_transport() {
+        let response = send_request(
+            "GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            ConnectionInfo::default(),
+        );
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Sample pages"));
+    }
+
+    #[test]
+    fn options_asterisk_lists_server_methods() {
+        let response = send_request("OPTIONS * HTTP/1.1\r\n\r\n", ConnectionInfo::default());
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("allow: GET, HEAD, POST, OPTIONS\r\n"));
+    }
+
+    #[test]
+    fn cgi_programs_see_tls_connections() {
+        let connection = ConnectionInfo {
+            peer_addr: Some("192.0.2.1:50000".parse().unwrap()),
+            local_addr: Some("192.0.2.2:443".parse().unwrap()),
+            tls: Some(TlsInfo::default()),
+            unix_socket: None,
+        };
+        let response = send_request(
+            "GET /cgi-bin/bash_document.sh HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            connection,
+        );
+
+        assert!(response.contains("HTTPS=on"));
+        assert!(response.contains("REMOTE_ADDR=192.0.2.1"));
+        assert!(response.contains("SERVER_PORT=443"));
+    }
+
+    /// Rejects every request, as an access control middleware would
+    struct Forbidden;
+
+    impl Middleware for Forbidden {
+        fn before(
+            &self,
+            _connection: &ConnectionInfo,
+            _request: &mut Request<String>,
+        ) -> Option<Response<String>> {
+            Some(generate_error_response(StatusCode::FORBIDDEN))
+        }
+    }
+
+    #[test]
+    fn middlewares_run_around_the_dispatch_in_order() {
+        let server_header = || {
+            Arc::new(ResponseHeaders::new(vec![(
+                http::header::SERVER,
+                HeaderValue::from_static("rust-web-cgi"),
+            )]))
+        };
+        let request = || {
+            Request::builder()
+                .uri("/index.html")
+                .body(String::from(""))
+                .unwrap()
+        };
+
+        let handler = sample_handler_with(vec![server_header(), Arc::new(Forbidden)]);
+        let response = handler.handle_request(request(), &ConnectionInfo::default());
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers()["server"], "rust-web-cgi");
 
-        info!("Finished writing response");
+        // Middlewares after the one answering the request are skipped
+        let handler = sample_handler_with(vec![Arc::new(Forbidden), server_header()]);
+        let response = handler.handle_request(request(), &ConnectionInfo::default());
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(response.headers().get("server").is_none());
     }
 }
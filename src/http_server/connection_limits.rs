@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+/// Reason why a connection was turned away by a `ConnectionLimiter`
+///
+#[derive(Debug, PartialEq)]
+pub enum LimitExceeded {
+    /// Too many connections are open in total
+    Total,
+    /// Too many connections are open from the client's address
+    PerClient(IpAddr),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Total => write!(f, "too many open connections"),
+            LimitExceeded::PerClient(ip) => write!(f, "too many open connections from {ip}"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct OpenConnections {
+    total: usize,
+    per_client: HashMap<IpAddr, usize>,
+}
+
+/// Counts the connections open on every listener, so that a single client
+/// cannot take up every worker thread. Connections without a remote address
+/// (over Unix domain sockets) only count towards the total.
+///
+pub struct ConnectionLimiter {
+    max_connections: usize,
+    max_connections_per_ip: usize,
+    open: Mutex<OpenConnections>,
+}
+
+/// An open connection counted by a `ConnectionLimiter`, for as long as the
+/// permit is alive
+///
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    ip: Option<IpAddr>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_connections: usize, max_connections_per_ip: usize) -> ConnectionLimiter {
+        ConnectionLimiter {
+            max_connections,
+            max_connections_per_ip,
+            open: Mutex::new(OpenConnections::default()),
+        }
+    }
+
+    /// Counts a new connection from the given address, unless one of the
+    /// limits is reached
+    ///
+    pub fn acquire(
+        self: &Arc<Self>,
+        ip: Option<IpAddr>,
+    ) -> Result<ConnectionPermit, LimitExceeded> {
+        let mut open = self.open.lock().unwrap_or_else(|error| error.into_inner());
+
+        if open.total >= self.max_connections {
+            return Err(LimitExceeded::Total);
+        }
+        if let Some(ip) = ip {
+            let count = open.per_client.entry(ip).or_insert(0);
+            if *count >= self.max_connections_per_ip {
+                return Err(LimitExceeded::PerClient(ip));
+            }
+            *count += 1;
+        }
+        open.total += 1;
+
+        Ok(ConnectionPermit {
+            limiter: Arc::clone(self),
+            ip,
+        })
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut open = self
+            .limiter
+            .open
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+
+        open.total -= 1;
+        if let Some(ip) = self.ip {
+            if let Some(count) = open.per_client.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    open.per_client.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_are_limited_per_client_and_in_total() {
+        let limiter = Arc::new(ConnectionLimiter::new(3, 2));
+        let first: IpAddr = "192.0.2.1".parse().unwrap();
+        let second: IpAddr = "192.0.2.2".parse().unwrap();
+
+        let permit = limiter.acquire(Some(first)).unwrap();
+        let _other_permit = limiter.acquire(Some(first)).unwrap();
+        assert_eq!(
+            limiter.acquire(Some(first)).err(),
+            Some(LimitExceeded::PerClient(first))
+        );
+
+        let _unix_permit = limiter.acquire(None).unwrap();
+        assert_eq!(
+            limiter.acquire(Some(second)).err(),
+            Some(LimitExceeded::Total)
+        );
+
+        drop(permit);
+        assert!(limiter.acquire(Some(first)).is_ok());
+    }
+}
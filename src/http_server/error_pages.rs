@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+
+use http::StatusCode;
+
+/// Maps HTTP status codes to the local URL paths of the documents to be sent
+/// instead of the built-in error page. A document can be either a static file
+/// or a CGI script, since it is resolved by the request handlers just like
+/// any other request.
+///
+#[derive(Clone, Debug, Default)]
+pub struct ErrorPages {
+    documents: HashMap<StatusCode, String>,
+}
+
+impl ErrorPages {
+    pub fn new() -> ErrorPages {
+        ErrorPages::default()
+    }
+
+    /// Sets the document to be sent for responses with the given status. The
+    /// location is a local URL path, such as `/errors/404.html` or
+    /// `/cgi-bin/error.sh`.
+    ///
+    pub fn insert(&mut self, status: StatusCode, location: String) {
+        self.documents.insert(status, location);
+    }
+
+    /// Returns the location of the error document for the given status, if
+    /// one was configured.
+    ///
+    pub fn get(&self, status: StatusCode) -> Option<&str> {
+        self.documents
+            .get(&status)
+            .map(|location| location.as_str())
+    }
+}
+
+impl<const N: usize> From<[(StatusCode, String); N]> for ErrorPages {
+    fn from(documents: [(StatusCode, String); N]) -> ErrorPages {
+        ErrorPages {
+            documents: HashMap::from(documents),
+        }
+    }
+}
@@ -0,0 +1,811 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::IpAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, warn};
+
+use mio::{
+    event::Source,
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+    Events, Interest, Poll, Registry, Token, Waker,
+};
+
+use http::StatusCode;
+
+use rustls::ServerConnection;
+
+use crate::{
+    http_server::{
+        connection::ConnectionHandler,
+        connection_limits::{ConnectionLimiter, ConnectionPermit},
+        listener::{Listener, ServedListener, OVERLOAD_RETRY_AFTER},
+        request::request::expected_request_length,
+        response::{generate_service_unavailable_response, response_to_string},
+        transport::{ConnectionInfo, TlsInfo},
+    },
+    threadpool::ThreadPool,
+};
+
+/// Token of the waker used by the workers to hand the responses back
+const WAKER: Token = Token(usize::MAX);
+
+/// Interval at which the event loop checks whether the server is shutting
+/// down while no event arrives
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+const READ_CHUNK_SIZE: usize = 4096;
+
+enum Acceptor {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+struct ListenerEntry {
+    acceptor: Acceptor,
+    address: String,
+    conn_handler: Arc<ConnectionHandler>,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+}
+
+enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Socket {
+    fn source(&mut self) -> &mut dyn Source {
+        match self {
+            Socket::Tcp(stream) => stream,
+            Socket::Unix(stream) => stream,
+        }
+    }
+
+    /// Returns the IP address of the client, or `None` for Unix domain
+    /// socket connections
+    ///
+    fn remote_ip(&self) -> Option<IpAddr> {
+        match self {
+            Socket::Tcp(stream) => stream
+                .peer_addr()
+                .ok()
+                .map(|address| address.ip().to_canonical()),
+            Socket::Unix(_) => None,
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.read(buf),
+            Socket::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.write(buf),
+            Socket::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.flush(),
+            Socket::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// The request is being received
+    Reading,
+    /// The request is being handled by a worker
+    Handling,
+    /// The response is being sent
+    Writing,
+}
+
+/// A client connection multiplexed by the event loop
+///
+struct Connection {
+    socket: Socket,
+    tls: Option<ServerConnection>,
+    /// Handler of the listener which accepted the connection
+    conn_handler: Arc<ConnectionHandler>,
+    state: State,
+    interest: Interest,
+    input: Vec<u8>,
+    headers_received: bool,
+    output: Vec<u8>,
+    written: usize,
+    /// Time at which the connection times out, unless its request is being
+    /// handled. It is pushed back whenever part of the response is sent.
+    deadline: Instant,
+    /// Counts the connection against the connection limits until it is
+    /// closed
+    _permit: ConnectionPermit,
+}
+
+impl Connection {
+    fn connection_info(&self) -> ConnectionInfo {
+        let mut info = match &self.socket {
+            Socket::Tcp(stream) => ConnectionInfo {
+                peer_addr: stream.peer_addr().ok(),
+                local_addr: stream.local_addr().ok(),
+                ..ConnectionInfo::default()
+            },
+            Socket::Unix(stream) => ConnectionInfo {
+                unix_socket: Some(
+                    stream
+                        .local_addr()
+                        .ok()
+                        .and_then(|address| address.as_pathname().map(PathBuf::from))
+                        .unwrap_or_default(),
+                ),
+                ..ConnectionInfo::default()
+            },
+        };
+        info.tls = self.tls.as_ref().map(|tls| TlsInfo {
+            server_name: tls.server_name().map(String::from),
+        });
+
+        info
+    }
+
+    /// Reads the data available on the socket, decrypting it if the
+    /// connection uses TLS. Returns whether the client closed the connection.
+    ///
+    fn read_input(&mut self, max_size: usize) -> io::Result<bool> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+
+        loop {
+            if self.input.len() > max_size {
+                return Ok(false);
+            }
+
+            let tls = match &mut self.tls {
+                None => match self.socket.read(&mut chunk) {
+                    Ok(0) => return Ok(true),
+                    Ok(read) => {
+                        self.input.extend_from_slice(&chunk[..read]);
+                        continue;
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(error) => return Err(error),
+                },
+                Some(tls) => tls,
+            };
+
+            match tls.read_tls(&mut self.socket) {
+                Ok(0) => return Ok(true),
+                Ok(_) => {}
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+            tls.process_new_packets().map_err(io::Error::other)?;
+
+            loop {
+                match tls.reader().read(&mut chunk) {
+                    Ok(0) => return Ok(true),
+                    Ok(read) => self.input.extend_from_slice(&chunk[..read]),
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+    }
+
+    /// Checks whether the whole request was received. The deadline of the
+    /// connection is moved to the end of the body timeout once the headers
+    /// are received. Returns the error status to be sent back if the request
+    /// is too large.
+    ///
+    fn request_received(&mut self) -> Result<bool, StatusCode> {
+        let limits = self.conn_handler.limits();
+        let expected_length = expected_request_length(&self.input);
+
+        if expected_length.is_some() && !self.headers_received {
+            self.headers_received = true;
+            self.deadline = Instant::now() + limits.body_timeout;
+        }
+
+        match expected_length {
+            Some(length) if length > limits.max_request_size => Err(StatusCode::PAYLOAD_TOO_LARGE),
+            Some(length) => Ok(self.input.len() >= length),
+            None => Ok(self.input.len() > limits.max_request_size),
+        }
+    }
+
+    /// Queues the response to be sent, encrypting it if the connection uses
+    /// TLS. The TLS session is closed after the response.
+    ///
+    fn set_response(&mut self, response: Vec<u8>) -> io::Result<()> {
+        self.state = State::Writing;
+        self.deadline = Instant::now() + self.conn_handler.limits().write_timeout;
+        match &mut self.tls {
+            None => self.output = response,
+            Some(tls) => {
+                tls.writer().write_all(&response)?;
+                tls.send_close_notify();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes as much pending data as the socket accepts. Returns whether
+    /// everything was written.
+    ///
+    fn write_output(&mut self) -> io::Result<bool> {
+        while let Some(tls) = self.tls.as_mut().filter(|tls| tls.wants_write()) {
+            match tls.write_tls(&mut self.socket) {
+                Ok(_) => self.extend_deadline(),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        while self.written < self.output.len() {
+            match self.socket.write(&self.output[self.written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.written += written;
+                    self.extend_deadline();
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Pushes the deadline back after part of the response was sent
+    ///
+    fn extend_deadline(&mut self) {
+        if self.state == State::Writing {
+            self.deadline = Instant::now() + self.conn_handler.limits().write_timeout;
+        }
+    }
+
+    /// Returns the events the connection is waiting for
+    ///
+    fn wanted_interest(&self) -> Interest {
+        let tls_wants_write = self.tls.as_ref().is_some_and(|tls| tls.wants_write());
+
+        match self.state {
+            State::Writing => Interest::WRITABLE,
+            _ if tls_wants_write => Interest::READABLE | Interest::WRITABLE,
+            _ => Interest::READABLE,
+        }
+    }
+
+    fn update_interest(&mut self, registry: &Registry, token: Token) -> io::Result<()> {
+        let interest = self.wanted_interest();
+        if interest != self.interest {
+            registry.reregister(self.socket.source(), token, interest)?;
+            self.interest = interest;
+        }
+
+        Ok(())
+    }
+}
+
+/// Turns away a connection exceeding the connection limits with a 503
+/// Service Unavailable response. The response is only sent if the socket
+/// accepts it right away, since the connection is not kept.
+///
+fn reject_connection(mut socket: Socket) {
+    let response = response_to_string(generate_service_unavailable_response(OVERLOAD_RETRY_AFTER));
+    if let Err(error) = socket.write_all(response.as_bytes()) {
+        debug!("Error rejecting a connection: {error}");
+    }
+}
+
+/// Event-driven server, multiplexing the connections of every listener on a
+/// single thread. Connections only take up a worker of the pool while their
+/// request is being handled, that is while reading files and running CGI
+/// programs, and not while waiting for slow clients.
+///
+pub struct EventLoop {
+    poll: Poll,
+    listeners: Vec<ListenerEntry>,
+    connections: HashMap<Token, Connection>,
+    next_token: usize,
+    pool: Arc<ThreadPool>,
+    limiter: Arc<ConnectionLimiter>,
+    waker: Arc<Waker>,
+    responses: mpsc::Receiver<(Token, String)>,
+    response_sender: mpsc::Sender<(Token, String)>,
+}
+
+impl EventLoop {
+    /// Sets up an event loop serving the given listeners, handing the
+    /// requests to the given pool. Connections exceeding the limits of
+    /// `limiter` are turned away.
+    ///
+    pub fn new(
+        listeners: Vec<ServedListener>,
+        pool: Arc<ThreadPool>,
+        limiter: Arc<ConnectionLimiter>,
+    ) -> io::Result<EventLoop> {
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let (response_sender, responses) = mpsc::channel();
+
+        let mut entries = vec![];
+        for (index, served) in listeners.into_iter().enumerate() {
+            let address = served.listener.describe();
+            let mut acceptor = match served.listener {
+                Listener::Tcp(listener) => {
+                    listener.set_nonblocking(true)?;
+                    Acceptor::Tcp(TcpListener::from_std(listener))
+                }
+                Listener::Unix(listener, _) => {
+                    listener.set_nonblocking(true)?;
+                    Acceptor::Unix(UnixListener::from_std(listener))
+                }
+            };
+            let source: &mut dyn Source = match &mut acceptor {
+                Acceptor::Tcp(listener) => listener,
+                Acceptor::Unix(listener) => listener,
+            };
+            poll.registry()
+                .register(source, Token(index), Interest::READABLE)?;
+            info!("Listening on {address}");
+
+            entries.push(ListenerEntry {
+                acceptor,
+                address,
+                conn_handler: served.conn_handler,
+                tls_config: served.tls_config,
+            });
+        }
+
+        Ok(EventLoop {
+            poll,
+            next_token: entries.len(),
+            listeners: entries,
+            connections: HashMap::new(),
+            pool,
+            limiter,
+            waker,
+            responses,
+            response_sender,
+        })
+    }
+
+    /// Serves the connections until the `shutdown` flag is raised. The
+    /// listeners are then closed, and the requests being handled are given
+    /// `shutdown_timeout` to complete.
+    ///
+    pub fn run(mut self, shutdown: Arc<AtomicBool>, shutdown_timeout: Duration) {
+        let mut events = Events::with_capacity(1024);
+        let mut deadline = None;
+
+        loop {
+            if deadline.is_none() && shutdown.load(Ordering::Relaxed) {
+                deadline = Some(Instant::now() + shutdown_timeout);
+                self.stop_listening();
+            }
+            if let Some(deadline) = deadline {
+                if self.connections.is_empty() || Instant::now() >= deadline {
+                    break;
+                }
+            }
+
+            if let Err(error) = self.poll.poll(&mut events, Some(POLL_TIMEOUT)) {
+                if error.kind() != io::ErrorKind::Interrupted {
+                    error!("Error waiting for events: {error}");
+                    break;
+                }
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    WAKER => self.send_responses(),
+                    Token(index) if index < self.listeners.len() => self.accept(index),
+                    token => self.process(token),
+                }
+            }
+            self.expire_connections();
+        }
+
+        if !self.connections.is_empty() {
+            warn!(
+                "{} connections did not complete in time",
+                self.connections.len()
+            );
+        }
+    }
+
+    /// Closes the listeners, and the connections whose request is not
+    /// received yet
+    ///
+    fn stop_listening(&mut self) {
+        for entry in self.listeners.drain(..) {
+            info!("Stopped listening on {}", entry.address);
+        }
+        self.connections
+            .retain(|_, connection| connection.state != State::Reading);
+    }
+
+    fn accept(&mut self, index: usize) {
+        loop {
+            let entry = &self.listeners[index];
+            let accepted = match &entry.acceptor {
+                Acceptor::Tcp(listener) => listener.accept().map(|(s, _)| Socket::Tcp(s)),
+                Acceptor::Unix(listener) => listener.accept().map(|(s, _)| Socket::Unix(s)),
+            };
+            let mut socket = match accepted {
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return,
+                Err(error) => {
+                    error!("Error accepting a connection on {}: {error}", entry.address);
+                    return;
+                }
+                Ok(socket) => socket,
+            };
+
+            let permit = match self.limiter.acquire(socket.remote_ip()) {
+                Err(error) => {
+                    warn!("Rejecting a connection on {}: {error}", entry.address);
+                    if entry.tls_config.is_none() {
+                        reject_connection(socket);
+                    }
+                    continue;
+                }
+                Ok(permit) => permit,
+            };
+
+            let tls =
+                match entry.tls_config.as_ref().map(|config| {
+                    ServerConnection::new(Arc::clone(config)).map_err(io::Error::other)
+                }) {
+                    Some(Err(error)) => {
+                        warn!("Could not set up a TLS session: {error}");
+                        continue;
+                    }
+                    Some(Ok(mut tls)) => {
+                        // The whole response is handed to the TLS session at once
+                        tls.set_buffer_limit(None);
+                        Some(tls)
+                    }
+                    None => None,
+                };
+
+            let conn_handler = Arc::clone(&entry.conn_handler);
+            let deadline = Instant::now() + conn_handler.limits().header_timeout;
+            let token = Token(self.next_token);
+            self.next_token += 1;
+            if let Err(error) =
+                self.poll
+                    .registry()
+                    .register(socket.source(), token, Interest::READABLE)
+            {
+                error!("Could not register a connection: {error}");
+                continue;
+            }
+
+            self.connections.insert(
+                token,
+                Connection {
+                    socket,
+                    tls,
+                    conn_handler,
+                    state: State::Reading,
+                    interest: Interest::READABLE,
+                    input: vec![],
+                    headers_received: false,
+                    output: vec![],
+                    written: 0,
+                    deadline,
+                    _permit: permit,
+                },
+            );
+        }
+    }
+
+    /// Handles an event on a client connection
+    ///
+    fn process(&mut self, token: Token) {
+        let result = match self.connections.get(&token).map(|c| c.state) {
+            None => return,
+            Some(State::Reading) => self.receive(token),
+            Some(State::Handling) => Ok(false),
+            Some(State::Writing) => self.flush(token),
+        };
+
+        self.conclude(token, result);
+    }
+
+    /// Closes a connection if it is done or failed, given the result of the
+    /// last operation on it
+    ///
+    fn conclude(&mut self, token: Token, result: io::Result<bool>) {
+        match result {
+            Ok(false) => {}
+            Ok(true) => self.close(token),
+            Err(error) => {
+                debug!("Connection error: {error}");
+                self.close(token);
+            }
+        }
+    }
+
+    /// Reads the request data received on a connection, and hands the
+    /// request to a worker once it is complete. Returns whether the
+    /// connection is done.
+    ///
+    fn receive(&mut self, token: Token) -> io::Result<bool> {
+        let connection = self.connections.get_mut(&token).unwrap();
+        let max_size = connection.conn_handler.limits().max_request_size;
+
+        let closed = connection.read_input(max_size)?;
+        let received = match connection.request_received() {
+            Err(status) => return self.dispatch(token, Err(status)),
+            Ok(received) => received,
+        };
+        if !received && !closed {
+            // The TLS handshake may need to send data to the client
+            connection.write_output()?;
+            connection.update_interest(self.poll.registry(), token)?;
+            return Ok(false);
+        }
+        if connection.input.is_empty() {
+            return Ok(true);
+        }
+
+        let input = std::mem::take(&mut connection.input);
+        self.dispatch(token, Ok(input))
+    }
+
+    /// Hands a received request to a worker, which sends the response back
+    /// to the event loop. The request may also have failed to be received,
+    /// in which case the worker builds the error response. Returns whether
+    /// the connection is done.
+    ///
+    fn dispatch(&mut self, token: Token, request: Result<Vec<u8>, StatusCode>) -> io::Result<bool> {
+        let connection = self.connections.get_mut(&token).unwrap();
+
+        let conn_handler = Arc::clone(&connection.conn_handler);
+        let connection_info = connection.connection_info();
+        let sender = self.response_sender.clone();
+        let waker = Arc::clone(&self.waker);
+        let job = move |request| {
+            let response = match request {
+                Ok(data) => conn_handler.handle_request_data(data, &connection_info),
+                Err(status) => conn_handler.respond(Err(status), &connection_info),
+            };
+            if sender.send((token, response)).is_ok() {
+                if let Err(error) = waker.wake() {
+                    error!("Could not wake up the event loop: {error}");
+                }
+            }
+        };
+
+        if self.pool.try_execute(request, job).is_err() {
+            warn!("Job queue is full, rejecting a connection");
+            let response = generate_service_unavailable_response(OVERLOAD_RETRY_AFTER);
+            connection.set_response(response_to_string(response).into_bytes())?;
+            return self.flush(token);
+        }
+
+        connection.state = State::Handling;
+        connection.update_interest(self.poll.registry(), token)?;
+
+        Ok(false)
+    }
+
+    /// Answers the connections too slow to send their request with a REQUEST
+    /// TIMEOUT response, and closes the connections not receiving their
+    /// response. TLS connections which didn't complete their handshake are
+    /// closed right away.
+    ///
+    fn expire_connections(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<(Token, State)> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| {
+                connection.state != State::Handling && connection.deadline <= now
+            })
+            .map(|(token, connection)| (*token, connection.state))
+            .collect();
+
+        for (token, state) in expired {
+            let handshaking = self.connections[&token]
+                .tls
+                .as_ref()
+                .is_some_and(|tls| tls.is_handshaking());
+
+            if state == State::Reading && !handshaking {
+                debug!("Timed out reading the request");
+                let result = self.dispatch(token, Err(StatusCode::REQUEST_TIMEOUT));
+                self.conclude(token, result);
+            } else {
+                debug!("Connection timed out");
+                self.close(token);
+            }
+        }
+    }
+
+    /// Queues the responses built by the workers for sending
+    ///
+    fn send_responses(&mut self) {
+        while let Ok((token, response)) = self.responses.try_recv() {
+            let result = match self.connections.get_mut(&token) {
+                None => continue,
+                Some(connection) => connection.set_response(response.into_bytes()),
+            };
+
+            let result = result.and_then(|_| self.flush(token));
+            self.conclude(token, result);
+        }
+    }
+
+    /// Writes the pending response of a connection. Returns whether the
+    /// whole response was sent.
+    ///
+    fn flush(&mut self, token: Token) -> io::Result<bool> {
+        let connection = self.connections.get_mut(&token).unwrap();
+        if connection.write_output()? {
+            return Ok(true);
+        }
+
+        connection.update_interest(self.poll.registry(), token)?;
+        Ok(false)
+    }
+
+    fn close(&mut self, token: Token) {
+        if let Some(mut connection) = self.connections.remove(&token) {
+            if let Err(error) = self.poll.registry().deregister(connection.socket.source()) {
+                debug!("Error closing a connection: {error}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        net::{Shutdown, SocketAddr},
+        thread::{self, JoinHandle},
+    };
+
+    use http::{Method, Request, Response};
+
+    use crate::http_server::{
+        error_pages::ErrorPages,
+        request::request::{RequestHandler, RequestLimits},
+        router::{RoutePattern, Router},
+        virtual_host::{VirtualHost, VirtualHosts},
+    };
+
+    /// Answers with a body of as many bytes as given by the request path
+    ///
+    struct SizedResponse;
+
+    impl RequestHandler<String> for SizedResponse {
+        fn handle_request(
+            &self,
+            _connection: &ConnectionInfo,
+            request: &Request<String>,
+        ) -> Option<Response<String>> {
+            let size = request.uri().path()[1..].parse().ok()?;
+            Response::builder()
+                .header("content-length", size)
+                .body("x".repeat(size))
+                .ok()
+        }
+
+        fn allowed_methods(&self) -> Vec<Method> {
+            vec![Method::GET]
+        }
+    }
+
+    /// Runs an event loop on a loopback listener, until the returned flag is
+    /// raised
+    ///
+    fn start_event_loop(limits: RequestLimits) -> (SocketAddr, Arc<AtomicBool>, JoinHandle<()>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let mut router = Router::new();
+        router.add(
+            RoutePattern::Prefix(String::from("/")),
+            vec![],
+            Box::new(SizedResponse),
+        );
+        let virtual_hosts = VirtualHosts::new(VirtualHost::new(router, ErrorPages::new()));
+        let served = ServedListener {
+            listener: Listener::Tcp(listener),
+            conn_handler: Arc::new(ConnectionHandler::new(virtual_hosts, limits, vec![])),
+            tls_config: None,
+        };
+        let pool = Arc::new(ThreadPool::new(1, 1, 4, Duration::from_secs(1)));
+        let limiter = Arc::new(ConnectionLimiter::new(16, 16));
+        let event_loop = EventLoop::new(vec![served], pool, limiter).unwrap();
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&shutdown);
+        let thread = thread::spawn(move || event_loop.run(flag, Duration::from_secs(1)));
+
+        (address, shutdown, thread)
+    }
+
+    fn connect(address: SocketAddr, request: &str) -> std::net::TcpStream {
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+
+        stream
+    }
+
+    #[test]
+    fn keep_alive_requests_are_answered_and_closed() {
+        let (address, shutdown, thread) = start_event_loop(RequestLimits::default());
+
+        let mut stream = connect(
+            address,
+            "GET /5 HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n",
+        );
+        // Connections serve a single request, so reading ends once the
+        // server closes the connection
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nxxxxx"));
+
+        shutdown.store(true, Ordering::Relaxed);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn clients_not_receiving_the_response_are_disconnected() {
+        let limits = RequestLimits {
+            write_timeout: Duration::from_millis(200),
+            ..RequestLimits::default()
+        };
+        let (address, shutdown, thread) = start_event_loop(limits);
+
+        // The response is far larger than what the socket buffers can hold
+        let size = 64 * 1024 * 1024;
+        let mut stream = connect(address, &format!("GET /{size} HTTP/1.1\r\n\r\n"));
+        stream.shutdown(Shutdown::Write).unwrap();
+        thread::sleep(Duration::from_secs(1));
+
+        let mut received = 0;
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        while let Ok(read) = stream.read(&mut chunk) {
+            if read == 0 {
+                break;
+            }
+            received += read;
+        }
+        assert!(received < size);
+
+        shutdown.store(true, Ordering::Relaxed);
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,544 @@
+use std::{
+    fs,
+    io::{self, Write},
+    net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    os::{
+        fd::{AsRawFd, FromRawFd, RawFd},
+        unix::{
+            fs::{FileTypeExt, PermissionsExt},
+            net::{UnixListener, UnixStream},
+            process::CommandExt,
+        },
+    },
+    path::{Path, PathBuf},
+    process::{self, Command},
+    sync::Arc,
+};
+
+use log::{debug, error, info, warn};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{
+    http_server::{
+        connection::ConnectionHandler,
+        connection_limits::ConnectionLimiter,
+        response::{generate_service_unavailable_response, response_to_string},
+        tls,
+    },
+    threadpool::ThreadPool,
+};
+
+const LISTEN_BACKLOG: i32 = 128;
+
+/// Environment variable through which a server process hands its listening
+/// sockets over to the process replacing it, as a comma-separated list of
+/// file descriptors
+pub const LISTEN_FDS_VAR: &str = "RUST_WEB_CGI_LISTEN_FDS";
+
+/// Environment variable through which a server process tells the process
+/// replacing it its process id, so that it can be asked to shut down
+pub const REPLACED_PID_VAR: &str = "RUST_WEB_CGI_REPLACED_PID";
+
+/// Delay after which clients turned away because the server is overloaded
+/// are asked to retry, in seconds
+pub(crate) const OVERLOAD_RETRY_AFTER: u64 = 5;
+
+/// Wakes up the threads waiting for connections when the server shuts down.
+/// It is a socket pair, the read end of which becomes readable once notified
+/// and stays so, which the listener threads wait for along with their
+/// listener.
+///
+pub struct ShutdownNotifier {
+    reader: UnixStream,
+    writer: UnixStream,
+}
+
+impl ShutdownNotifier {
+    pub fn new() -> io::Result<ShutdownNotifier> {
+        let (reader, writer) = UnixStream::pair()?;
+        Ok(ShutdownNotifier { reader, writer })
+    }
+
+    /// Asks the listener threads to stop accepting connections
+    ///
+    pub fn notify(&self) {
+        if let Err(error) = (&self.writer).write_all(&[1]) {
+            error!("Could not notify the listeners of the shutdown: {error}");
+        }
+    }
+
+    /// Waits until a connection arrives at the given listener, returning
+    /// `true`, or until the server shuts down, returning `false`
+    ///
+    fn wait_for_connection(&self, listener: &Listener) -> io::Result<bool> {
+        let mut fds = [
+            libc::pollfd {
+                fd: listener.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.reader.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+
+        loop {
+            // SAFETY: the pointer and length describe the `fds` array
+            let result = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+            if result >= 0 {
+                return Ok(fds[1].revents == 0);
+            }
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+    }
+}
+
+/// A socket accepting client connections
+///
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+/// A listener along with the handler of its connections and its TLS
+/// configuration, if any
+///
+pub struct ServedListener {
+    pub listener: Listener,
+    pub conn_handler: Arc<ConnectionHandler>,
+    pub tls_config: Option<Arc<rustls::ServerConfig>>,
+}
+
+/// A connection accepted by a `Listener`
+///
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Returns the IP address of the client, or `None` for Unix domain
+    /// socket connections
+    ///
+    fn remote_ip(&self) -> Option<IpAddr> {
+        match self {
+            Stream::Tcp(stream) => stream
+                .peer_addr()
+                .ok()
+                .map(|address| address.ip().to_canonical()),
+            Stream::Unix(_) => None,
+        }
+    }
+}
+
+impl Listener {
+    /// Accepts a connection. Accepted streams are always blocking, even if
+    /// the listener is not.
+    ///
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                Ok(Stream::Tcp(stream))
+            }
+            Listener::Unix(listener, _) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                Ok(Stream::Unix(stream))
+            }
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            Listener::Unix(listener, _) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Returns a description of the listening address, for logging purposes
+    ///
+    pub fn describe(&self) -> String {
+        match self {
+            Listener::Tcp(listener) => listener
+                .local_addr()
+                .map_or(String::from("unknown address"), |address| {
+                    address.to_string()
+                }),
+            Listener::Unix(_, path) => format!("unix:{}", path.display()),
+        }
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener, _) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// Listening sockets inherited from a previous server process, which are
+/// reused instead of opening new ones so that no connection is refused while
+/// the server is being replaced
+///
+#[derive(Default)]
+pub struct InheritedListeners {
+    tcp: Vec<TcpListener>,
+    unix: Vec<UnixListener>,
+}
+
+impl InheritedListeners {
+    /// Adopts the sockets listed in the value of the `LISTEN_FDS_VAR`
+    /// environment variable, if it is set
+    ///
+    pub fn from_env(listen_fds: Option<&str>) -> InheritedListeners {
+        let mut inherited = InheritedListeners::default();
+        let Some(fds) = listen_fds else {
+            return inherited;
+        };
+
+        for fd in fds.split(',').filter(|fd| !fd.is_empty()) {
+            let fd: RawFd = match fd.parse() {
+                Err(_) => {
+                    warn!("Invalid inherited file descriptor: {fd}");
+                    continue;
+                }
+                Ok(fd) => fd,
+            };
+            // SAFETY: the previous server process hands over the ownership of
+            // the listed sockets, which are not used anywhere else
+            let socket = unsafe { Socket::from_raw_fd(fd) };
+            // The sockets were made inheritable for this process only, and
+            // must not leak into the CGI programs
+            if let Err(error) = socket.set_cloexec(true) {
+                warn!("Could not set up inherited file descriptor {fd}: {error}");
+            }
+
+            match socket.local_addr() {
+                Ok(address) if address.is_unix() => inherited.unix.push(socket.into()),
+                Ok(address) if address.as_socket().is_some() => inherited.tcp.push(socket.into()),
+                _ => warn!("Inherited file descriptor {fd} is not a listening socket"),
+            }
+        }
+
+        inherited
+    }
+
+    /// Takes the inherited TCP listener bound to the given address, if any
+    ///
+    pub fn take_tcp(&mut self, address: SocketAddr) -> Option<TcpListener> {
+        let index = self
+            .tcp
+            .iter()
+            .position(|listener| listener.local_addr().is_ok_and(|a| a == address))?;
+        Some(self.tcp.swap_remove(index))
+    }
+
+    /// Takes the inherited Unix domain socket listener bound to the given
+    /// path, if any
+    ///
+    pub fn take_unix(&mut self, path: &Path) -> Option<UnixListener> {
+        let index = self.unix.iter().position(|listener| {
+            listener
+                .local_addr()
+                .is_ok_and(|a| a.as_pathname() == Some(path))
+        })?;
+        Some(self.unix.swap_remove(index))
+    }
+}
+
+/// Sets up the command starting a new server process so that it inherits
+/// the given listening sockets, and learns the process id of this one
+///
+pub fn hand_over(command: &mut Command, listener_fds: &[RawFd]) {
+    let fds: Vec<String> = listener_fds.iter().map(|fd| fd.to_string()).collect();
+    let inherited_fds = listener_fds.to_vec();
+
+    command
+        .env(LISTEN_FDS_VAR, fds.join(","))
+        .env(REPLACED_PID_VAR, process::id().to_string());
+    // SAFETY: only calls fcntl, which is async-signal-safe
+    unsafe {
+        command.pre_exec(move || {
+            for fd in &inherited_fds {
+                if libc::fcntl(*fd, libc::F_SETFD, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Returns the process id of the server process replaced by this one, given
+/// the value of the `REPLACED_PID_VAR` environment variable. It is only
+/// returned while that process is still the parent of this one, since the id
+/// may belong to another process once it has exited.
+///
+pub fn replaced_process(replaced_pid: Option<&str>) -> Option<libc::pid_t> {
+    let pid: libc::pid_t = replaced_pid?.parse().ok()?;
+    // SAFETY: getppid has no memory safety requirements
+    let parent = unsafe { libc::getppid() };
+
+    (pid == parent).then_some(pid)
+}
+
+/// Opens a TCP listener on the given address. IPv6 listeners are dual-stack
+/// (also accepting IPv4 connections through IPv4-mapped addresses) unless
+/// `ipv6_only` is set.
+///
+pub fn bind(address: SocketAddr, ipv6_only: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+
+    if address.is_ipv6() {
+        socket.set_only_v6(ipv6_only)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    Ok(socket.into())
+}
+
+/// Opens a Unix domain socket listener at the given path, replacing a stale
+/// socket left by a previous run. The permissions of the socket file are set
+/// to `mode` if given.
+///
+pub fn bind_unix(path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a file which is not a socket already exists at this path",
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+
+    Ok(listener)
+}
+
+/// Turns away a connection with a 503 Service Unavailable response, when
+/// every worker is busy and the job queue is full, or when too many
+/// connections are open. Connections to TLS listeners are closed without a
+/// response, since answering them would require a TLS handshake.
+///
+fn reject_connection(stream: Stream, tls: bool) {
+    if tls {
+        return;
+    }
+
+    let response = response_to_string(generate_service_unavailable_response(OVERLOAD_RETRY_AFTER));
+
+    let result = match stream {
+        Stream::Tcp(mut stream) => stream
+            .write_all(response.as_bytes())
+            .and_then(|_| stream.shutdown(Shutdown::Write)),
+        Stream::Unix(mut stream) => stream
+            .write_all(response.as_bytes())
+            .and_then(|_| stream.shutdown(Shutdown::Write)),
+    };
+    if let Err(error) = result {
+        debug!("Error rejecting a connection: {error}");
+    }
+}
+
+/// Handles a connection accepted by a TLS listener: performs the TLS
+/// handshake, handles the request and closes the TLS session.
+///
+fn handle_tls_connection(
+    stream: TcpStream,
+    conn_handler: &ConnectionHandler,
+    tls_config: Arc<rustls::ServerConfig>,
+) {
+    // The handshake is subject to the same timeouts as the request
+    let limits = conn_handler.limits();
+    if let Err(error) = stream
+        .set_read_timeout(Some(limits.header_timeout))
+        .and_then(|_| stream.set_write_timeout(Some(limits.write_timeout)))
+    {
+        warn!("Could not set up the connection: {error}");
+        return;
+    }
+
+    let mut tls_stream = match tls::accept(stream, tls_config) {
+        Err(error) => {
+            warn!("TLS handshake failed: {error}");
+            return;
+        }
+        Ok(accepted) => accepted,
+    };
+
+    conn_handler.handle_connection(&mut tls_stream);
+
+    tls_stream.conn.send_close_notify();
+    if let Err(error) = tls_stream.flush() {
+        debug!("Error closing the TLS session: {error}");
+    }
+}
+
+/// Accepts the connections arriving at a listener, handing each of them to
+/// the shared thread pool to be handled by the given connection handler.
+/// Connections are encrypted if a TLS configuration is given, and turned
+/// away if they exceed the limits of `limiter`. Returns once `shutdown` is
+/// notified, closing the listener.
+///
+pub fn serve(
+    listener: Listener,
+    conn_handler: Arc<ConnectionHandler>,
+    pool: Arc<ThreadPool>,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    limiter: Arc<ConnectionLimiter>,
+    shutdown: Arc<ShutdownNotifier>,
+) {
+    let address = listener.describe();
+    // The listener is only used once a connection is waiting, but the
+    // connection may be gone by then
+    if let Err(error) = listener.set_nonblocking(true) {
+        error!("Could not set up the listener on {address}: {error}");
+        return;
+    }
+    info!("Listening on {address}");
+
+    loop {
+        match shutdown.wait_for_connection(&listener) {
+            Err(error) => {
+                error!("Error waiting for connections on {address}: {error}");
+                break;
+            }
+            Ok(false) => break,
+            Ok(true) => {}
+        }
+
+        let stream = match listener.accept() {
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(error) => {
+                error!("Error accepting a connection on {address}: {error}");
+                continue;
+            }
+            Ok(stream) => stream,
+        };
+
+        let permit = match limiter.acquire(stream.remote_ip()) {
+            Err(error) => {
+                warn!("Rejecting a connection on {address}: {error}");
+                reject_connection(stream, tls_config.is_some());
+                continue;
+            }
+            Ok(permit) => permit,
+        };
+
+        let handler_clone = Arc::clone(&conn_handler);
+        let job_tls_config = tls_config.clone();
+
+        let job = move |stream| {
+            // The connection is counted until it is closed
+            let _permit = permit;
+            match (stream, job_tls_config) {
+                (Stream::Tcp(stream), None) => (*handler_clone).handle_connection(stream),
+                (Stream::Tcp(stream), Some(tls_config)) => {
+                    handle_tls_connection(stream, &handler_clone, tls_config)
+                }
+                (Stream::Unix(stream), _) => (*handler_clone).handle_connection(stream),
+            }
+        };
+
+        if let Err(stream) = pool.try_execute(stream, job) {
+            let metrics = pool.metrics();
+            warn!(
+                "Job queue is full ({} workers, {} queued jobs), rejecting a connection on {address}",
+                metrics.size, metrics.queued
+            );
+            reject_connection(stream, tls_config.is_some());
+        }
+    }
+
+    info!("Stopped listening on {address}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    #[test]
+    fn listening_sockets_are_handed_over() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let fd = listener.as_raw_fd();
+
+        // The new process sees the sockets and the process id of this one
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!(
+            "test \"${LISTEN_FDS_VAR}\" = {fd} && test \"${REPLACED_PID_VAR}\" = {} \\
+            && test -e /proc/self/fd/{fd}",
+            process::id()
+        ));
+        hand_over(&mut command, &[fd]);
+        assert!(command.status().unwrap().success());
+    }
+
+    #[test]
+    fn inherited_sockets_are_not_passed_on() {
+        if let Ok(fds) = env::var(LISTEN_FDS_VAR) {
+            // In the new process started below: the socket is adopted, and
+            // isn't inherited by the processes this one starts
+            let inherited = InheritedListeners::from_env(Some(&fds));
+            assert_eq!(inherited.tcp.len(), 1);
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(format!("test ! -e /proc/self/fd/{fds}"))
+                .status()
+                .unwrap();
+            assert!(status.success());
+            return;
+        }
+
+        // Runs this test again in a new process adopting a socket, rather
+        // than adopting it here while other tests start processes
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut command = Command::new(env::current_exe().unwrap());
+        command
+            .arg("--exact")
+            .arg("http_server::listener::tests::inherited_sockets_are_not_passed_on")
+            .arg("--quiet");
+        hand_over(&mut command, &[listener.as_raw_fd()]);
+
+        let output = command.output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+    }
+
+    #[test]
+    fn only_the_parent_process_is_replaced() {
+        // SAFETY: getppid has no memory safety requirements
+        let parent = unsafe { libc::getppid() };
+
+        assert_eq!(replaced_process(Some(&parent.to_string())), Some(parent));
+        assert_eq!(replaced_process(Some(&(parent + 1).to_string())), None);
+        assert_eq!(replaced_process(Some("abc")), None);
+        assert_eq!(replaced_process(None), None);
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use http::{HeaderName, HeaderValue, Request, Response};
+
+use log::info;
+
+use crate::http_server::transport::ConnectionInfo;
+
+/// Names of the built-in middlewares, which can be listed in the
+/// `server.middleware` setting
+pub const BUILTIN_MIDDLEWARES: [&str; 3] = ["access_log", "rate_limit", "response_headers"];
+
+/// A policy applied to every request around its dispatch to the request
+/// handlers, such as access control, logging or extra response headers.
+///
+/// Middlewares are chained in a configured order: requests go through the
+/// `before` methods in that order, and responses go back through the `after`
+/// methods in the reverse order.
+///
+pub trait Middleware {
+    /// The `before` trait method may inspect and modify a request before it
+    /// is dispatched. Returning a response stops the request there, in which
+    /// case only the middlewares which already saw the request get to see
+    /// the response.
+    ///
+    fn before(
+        &self,
+        _connection: &ConnectionInfo,
+        _request: &mut Request<String>,
+    ) -> Option<Response<String>> {
+        None
+    }
+
+    /// The `after` trait method may inspect and modify the response to a
+    /// request, once error pages are applied.
+    ///
+    fn after(
+        &self,
+        _connection: &ConnectionInfo,
+        _request: &Request<String>,
+        _response: &mut Response<String>,
+    ) {
+    }
+}
+
+/// Middlewares are shared by the connection handlers of every listener, so
+/// that their state (such as rate limits) applies to the whole server
+pub type MiddlewareChain = Vec<Arc<dyn Middleware + Sync + Send>>;
+
+/// Logs every request along with the status of its response
+///
+pub struct AccessLog;
+
+impl Middleware for AccessLog {
+    fn after(
+        &self,
+        connection: &ConnectionInfo,
+        request: &Request<String>,
+        response: &mut Response<String>,
+    ) {
+        let client = connection
+            .remote_ip()
+            .map_or(String::from("-"), |ip| ip.to_string());
+        info!(
+            "{client} \"{} {}\" {}",
+            request.method(),
+            request.uri(),
+            response.status().as_u16()
+        );
+    }
+}
+
+/// Adds a fixed set of headers to every response, replacing the headers of
+/// the same name
+///
+pub struct ResponseHeaders {
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl ResponseHeaders {
+    pub fn new(headers: Vec<(HeaderName, HeaderValue)>) -> ResponseHeaders {
+        ResponseHeaders { headers }
+    }
+}
+
+impl Middleware for ResponseHeaders {
+    fn after(
+        &self,
+        _connection: &ConnectionInfo,
+        _request: &Request<String>,
+        response: &mut Response<String>,
+    ) {
+        for (name, value) in &self.headers {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use http::{Request, Response};
+
+use log::info;
+
+use crate::http_server::{
+    middleware::Middleware, response::generate_too_many_requests_response,
+    transport::ConnectionInfo,
+};
+
+/// Number of buckets above which the buckets which are full again are
+/// dropped, so that clients which stopped sending requests are forgotten.
+/// The buckets are then pruned again once their number has doubled.
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+/// A limit on the rate of the requests sent by each client, optionally
+/// restricted to the paths under a prefix
+///
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    /// URL path prefix to which the limit applies, or `None` for every path
+    pub path: Option<String>,
+    /// Number of requests allowed per second, on average
+    pub rate: f64,
+    /// Number of requests which can be sent at once
+    pub burst: u32,
+}
+
+impl RateLimit {
+    fn applies_to(&self, path: &str) -> bool {
+        let prefix = match &self.path {
+            None => return true,
+            Some(prefix) => prefix.trim_end_matches('/'),
+        };
+
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+/// Requests a client may still send, refilled over time up to the burst
+/// size of the limit
+///
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst as f64);
+        self.updated = now;
+    }
+}
+
+/// Buckets of the clients, by limit and client IP address
+///
+struct Buckets {
+    map: HashMap<(usize, IpAddr), TokenBucket>,
+    /// Number of buckets above which they are pruned
+    prune_above: usize,
+}
+
+/// Token bucket rate limiter, keyed by client IP address and limit. Each
+/// request takes a token from the bucket of every limit applying to it.
+///
+pub struct RateLimiter {
+    limits: Vec<RateLimit>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: Vec<RateLimit>) -> RateLimiter {
+        RateLimiter {
+            limits,
+            buckets: Mutex::new(Buckets {
+                map: HashMap::new(),
+                prune_above: MAX_TRACKED_BUCKETS,
+            }),
+        }
+    }
+
+    /// Counts a request from the given client to the given path. Returns
+    /// the time after which the client may retry if the request exceeds one
+    /// of the limits, in which case no token is taken.
+    ///
+    pub fn check(&self, ip: IpAddr, path: &str) -> Result<(), Duration> {
+        self.check_at(ip, path, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, path: &str, now: Instant) -> Result<(), Duration> {
+        let applying: Vec<usize> = (0..self.limits.len())
+            .filter(|index| self.limits[*index].applies_to(path))
+            .collect();
+        if applying.is_empty() {
+            return Ok(());
+        }
+
+        let mut guard = self
+            .buckets
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        let Buckets {
+            map: buckets,
+            prune_above,
+        } = &mut *guard;
+        if buckets.len() > *prune_above {
+            buckets.retain(|(index, _), bucket| {
+                bucket.refill(&self.limits[*index], now);
+                bucket.tokens < self.limits[*index].burst as f64
+            });
+            // Pruning takes time proportional to the number of buckets, so
+            // it is only done again once as many buckets were added
+            *prune_above = MAX_TRACKED_BUCKETS.max(buckets.len() * 2);
+        }
+
+        let mut retry_after = Duration::ZERO;
+        for index in &applying {
+            let limit = &self.limits[*index];
+            let bucket = buckets.entry((*index, ip)).or_insert(TokenBucket {
+                tokens: limit.burst as f64,
+                updated: now,
+            });
+            bucket.refill(limit, now);
+
+            if bucket.tokens < 1.0 {
+                let wait = Duration::from_secs_f64((1.0 - bucket.tokens) / limit.rate);
+                retry_after = retry_after.max(wait);
+            }
+        }
+        if !retry_after.is_zero() {
+            return Err(retry_after);
+        }
+
+        for index in &applying {
+            if let Some(bucket) = buckets.get_mut(&(*index, ip)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Middleware for RateLimiter {
+    /// Answers the requests of the clients exceeding the rate limits with a
+    /// TOO MANY REQUESTS response
+    ///
+    fn before(
+        &self,
+        connection: &ConnectionInfo,
+        request: &mut Request<String>,
+    ) -> Option<Response<String>> {
+        let ip = connection.remote_ip()?;
+        let retry_after = self.check(ip, request.uri().path()).err()?;
+
+        info!("Rate limit exceeded by {ip}");
+        // Retry-After is given in whole seconds
+        let retry_after = retry_after.as_secs_f64().ceil() as u64;
+        Some(generate_too_many_requests_response(retry_after))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_limited_per_client_and_path() {
+        let limiter = RateLimiter::new(vec![
+            RateLimit {
+                path: None,
+                rate: 10.0,
+                burst: 3,
+            },
+            RateLimit {
+                path: Some(String::from("/cgi-bin")),
+                rate: 1.0,
+                burst: 1,
+            },
+        ]);
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let other_client: IpAddr = "192.0.2.2".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(limiter.check_at(client, "/cgi-bin/a.sh", start).is_ok());
+        assert!(limiter.check_at(client, "/cgi-bin/a.sh", start).is_err());
+        assert!(limiter.check_at(client, "/cgi-bin-other", start).is_ok());
+        assert!(limiter.check_at(client, "/index.html", start).is_ok());
+        assert_eq!(
+            limiter.check_at(client, "/index.html", start),
+            Err(Duration::from_millis(100))
+        );
+        assert!(limiter.check_at(other_client, "/index.html", start).is_ok());
+
+        let later = start + Duration::from_secs(1);
+        assert!(limiter.check_at(client, "/cgi-bin/a.sh", later).is_ok());
+    }
+
+    #[test]
+    fn buckets_are_pruned_once_their_number_doubled() {
+        let limiter = RateLimiter::new(vec![RateLimit {
+            path: None,
+            rate: 1.0,
+            burst: 2,
+        }]);
+        let start = Instant::now();
+        let client = |n: usize| IpAddr::from([10, (n >> 16) as u8, (n >> 8) as u8, n as u8]);
+        let tracked = || limiter.buckets.lock().unwrap().map.len();
+
+        // None of the buckets is full again, so none of them is dropped
+        for n in 0..=MAX_TRACKED_BUCKETS {
+            limiter.check_at(client(n), "/", start).unwrap();
+        }
+        limiter
+            .check_at(client(MAX_TRACKED_BUCKETS + 1), "/", start)
+            .unwrap();
+        assert_eq!(tracked(), MAX_TRACKED_BUCKETS + 2);
+
+        // Pruning only happens again once the number of buckets doubled,
+        // even though every bucket is full again by then
+        let later = start + Duration::from_secs(2);
+        limiter.check_at(client(0), "/", later).unwrap();
+        assert_eq!(tracked(), MAX_TRACKED_BUCKETS + 2);
+        for n in MAX_TRACKED_BUCKETS + 2..=2 * (MAX_TRACKED_BUCKETS + 1) {
+            limiter.check_at(client(n), "/", later).unwrap();
+        }
+        let much_later = later + Duration::from_secs(2);
+        limiter
+            .check_at(client(2 * MAX_TRACKED_BUCKETS + 3), "/", much_later)
+            .unwrap();
+        assert_eq!(tracked(), 1);
+    }
+}
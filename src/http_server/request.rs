@@ -1,3 +1,4 @@
 pub mod cgi_request;
+#[allow(clippy::module_inception)]
 pub mod request;
 pub mod static_request;
@@ -1,35 +1,203 @@
 use std::{
-    fs,
-    io::Write,
-    net::TcpStream,
+    collections::HashMap,
+    env, fmt, fs,
+    io::{self, Read, Write},
+    mem,
+    os::unix::{fs::PermissionsExt, process::CommandExt},
     path::{Path, PathBuf},
     process::{Command, Stdio},
+    sync::{mpsc, Mutex},
+    thread,
+    time::Duration,
 };
 
-use http::{header, HeaderName, Request, Response, StatusCode};
+use http::{header, HeaderName, Method, Request, Response, StatusCode};
 
-use log::debug;
+use log::{debug, error, warn};
 
 use crate::http_server::{
     request::{
         cgi_request::{
             cgi_metavariables::CGIMetavariableMap,
-            cgi_response::{convert_cgi_response_to_http, parse_cgi_response},
+            cgi_response::{convert_cgi_response_to_http, parse_cgi_response, CgiResponseError},
         },
         request::RequestHandler,
         static_request::static_handler::StaticRequestHandler,
     },
-    response::generate_error_response,
+    response::{
+        generate_detailed_error_response, generate_error_response,
+        generate_method_not_allowed_response, generate_options_response,
+    },
+    transport::ConnectionInfo,
+    virtual_host::{request_host, request_port},
 };
 
 use super::cgi_metavariables::CGIMetavariable;
 
 const DEFAULT_PORT: &str = "80";
+const DEFAULT_TLS_PORT: &str = "443";
+
+/// Process ids of the CGI programs currently running
+static RUNNING_PROGRAMS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Registers a running CGI program for as long as it is alive, so that it
+/// can be killed when the server shuts down
+///
+struct RunningProgram(u32);
+
+impl RunningProgram {
+    fn register(pid: u32) -> RunningProgram {
+        RUNNING_PROGRAMS
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .push(pid);
+        RunningProgram(pid)
+    }
+}
+
+impl Drop for RunningProgram {
+    fn drop(&mut self) {
+        RUNNING_PROGRAMS
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .retain(|pid| *pid != self.0);
+    }
+}
+
+/// Kills the CGI programs still running, along with the processes they
+/// started (CGI programs run in their own process group), so that the
+/// requests waiting for them can complete. Used when shutting down the
+/// server. Returns the number of programs killed.
+///
+pub fn kill_running_programs() -> usize {
+    let running = RUNNING_PROGRAMS
+        .lock()
+        .unwrap_or_else(|error| error.into_inner());
+
+    for pid in running.iter() {
+        debug!("Killing CGI program {pid}");
+        // SAFETY: kill has no memory safety requirements
+        unsafe {
+            libc::kill(-(*pid as libc::pid_t), libc::SIGKILL);
+        }
+    }
+
+    running.len()
+}
+
+/// Settings applied to the CGI programs of a mount
+///
+#[derive(Clone, Debug, Default)]
+pub struct CgiOptions {
+    /// Environment variables set for the programs, along with the CGI
+    /// metavariables, which take precedence
+    pub env: HashMap<String, String>,
+    /// Environment variables of the server passed on to the programs, which
+    /// are overridden by `env` and the CGI metavariables. The variables the
+    /// server doesn't have are left unset.
+    pub pass_env: Vec<String>,
+    /// Time after which a program still running is killed, and the request
+    /// answered with a GATEWAY TIMEOUT response
+    pub timeout: Option<Duration>,
+    /// Program running the CGI programs, which receives the path of the
+    /// requested program as its argument
+    pub interpreter: Option<PathBuf>,
+    /// Programs running the CGI programs with a given extension (without
+    /// the leading dot), used when `interpreter` is not set. Other CGI
+    /// programs are run directly.
+    pub interpreters: HashMap<String, PathBuf>,
+    /// Shows the cause of CGI failures in the error pages sent to clients,
+    /// instead of only logging it
+    pub debug: bool,
+}
+
+impl CgiOptions {
+    /// Returns the interpreter running the given CGI program, if any
+    ///
+    fn interpreter_for(&self, script_path: &Path) -> Option<&PathBuf> {
+        self.interpreter.as_ref().or_else(|| {
+            let extension = script_path.extension()?.to_str()?;
+            self.interpreters.get(extension)
+        })
+    }
+}
+
+/// Reasons why a CGI program couldn't produce a response
+///
+#[derive(Debug)]
+pub enum CgiError {
+    /// The program is run directly but isn't executable
+    NotExecutable(PathBuf),
+    /// The interpreter of the program doesn't exist
+    InterpreterNotFound(PathBuf),
+    /// The program couldn't be started
+    Spawn(io::Error),
+    /// Passing the request body to the program or reading its output failed
+    Io(io::Error),
+    /// The program didn't complete within the configured timeout
+    TimedOut(Duration),
+    /// The output of the program isn't a valid CGI response
+    InvalidResponse(CgiResponseError),
+}
+
+impl CgiError {
+    /// Returns the status code of the response sent to the client
+    ///
+    pub fn status(&self) -> StatusCode {
+        match self {
+            CgiError::NotExecutable(_) => StatusCode::FORBIDDEN,
+            CgiError::TimedOut(_) => StatusCode::GATEWAY_TIMEOUT,
+            CgiError::InvalidResponse(_) => StatusCode::BAD_GATEWAY,
+            CgiError::InterpreterNotFound(_) | CgiError::Spawn(_) | CgiError::Io(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+impl fmt::Display for CgiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CgiError::NotExecutable(path) => write!(f, "{} is not executable", path.display()),
+            CgiError::InterpreterNotFound(interpreter) => {
+                write!(f, "interpreter {} not found", interpreter.display())
+            }
+            CgiError::Spawn(error) => write!(f, "couldn't start the program: {error}"),
+            CgiError::Io(error) => write!(f, "couldn't communicate with the program: {error}"),
+            CgiError::TimedOut(timeout) => {
+                write!(
+                    f,
+                    "the program didn't complete within {}s",
+                    timeout.as_secs()
+                )
+            }
+            CgiError::InvalidResponse(error) => write!(f, "malformed program output: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for CgiError {}
+
+/// Looks up a program such as a CGI interpreter. Paths with several
+/// components are used as they are, while bare program names are looked up
+/// in the `PATH` of the server, since CGI programs don't inherit it. Returns
+/// the path of the program if it exists.
+///
+pub fn find_program(program: &Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return program.is_file().then(|| program.to_path_buf());
+    }
+
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|folder| folder.join(program))
+        .find(|path| path.is_file())
+}
 
 pub struct CgiRequestHandler {
     cgi_path: String,
     cgi_folder: String,
     static_handler: StaticRequestHandler,
+    options: CgiOptions,
 }
 
 impl CgiRequestHandler {
@@ -37,11 +205,13 @@ impl CgiRequestHandler {
         cgi_path: String,
         cgi_folder: String,
         static_handler: StaticRequestHandler,
+        options: CgiOptions,
     ) -> CgiRequestHandler {
         CgiRequestHandler {
             cgi_path,
             cgi_folder,
             static_handler,
+            options,
         }
     }
 }
@@ -57,45 +227,160 @@ fn get_header_or_empty_string(request: &Request<String>, header_name: HeaderName
 }
 
 /// Runs the CGI program located at `script_path` with the given `input_data`,
-/// setting up the supplied environment variables. Returns the CGI program
-/// output if successful. Otherwise returns the error.
+/// setting up the supplied environment variables along with the ones of the
+/// mount options (the ones passed on from the server, then the configured
+/// ones). The program is run by the configured interpreter, if any,
+/// and killed along with the processes it started once the configured
+/// timeout expires. Returns the CGI program output if successful. Otherwise
+/// returns the error.
 ///
 fn run_process(
     script_path: PathBuf,
-    input_data: &String,
+    input_data: &str,
     env_variables: CGIMetavariableMap,
-) -> Result<String, Box<dyn std::error::Error>> {
+    options: &CgiOptions,
+) -> Result<String, CgiError> {
     let mut parent_folder = script_path.clone();
     parent_folder.pop();
-    let mut script_process = Command::new(script_path)
+
+    let interpreter = options.interpreter_for(&script_path);
+    let mut command = match interpreter {
+        None => {
+            let executable = fs::metadata(&script_path)
+                .is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0);
+            if !executable {
+                return Err(CgiError::NotExecutable(script_path));
+            }
+            Command::new(&script_path)
+        }
+        Some(interpreter) => {
+            let mut command = Command::new(interpreter);
+            command.arg(&script_path);
+            command
+        }
+    };
+    let spawned = command
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .env_clear()
         .current_dir(parent_folder)
+        .envs(
+            options
+                .pass_env
+                .iter()
+                .filter_map(|name| Some((name, env::var_os(name)?))),
+        )
+        .envs(&options.env)
         .envs(&env_variables)
-        .spawn()?;
+        .process_group(0)
+        .spawn();
+    let mut script_process = match (spawned, interpreter) {
+        (Ok(process), _) => process,
+        (Err(error), Some(interpreter)) if error.kind() == io::ErrorKind::NotFound => {
+            return Err(CgiError::InterpreterNotFound(interpreter.clone()));
+        }
+        (Err(error), None) if error.kind() == io::ErrorKind::PermissionDenied => {
+            return Err(CgiError::NotExecutable(script_path));
+        }
+        (Err(error), _) => return Err(CgiError::Spawn(error)),
+    };
+    let pid = script_process.id();
+    let running = RunningProgram::register(pid);
 
-    let mut stdin = script_process
+    let pipes = script_process
         .stdin
         .take()
-        .ok_or("Error getting stdin for child process")?;
-    stdin.write_all(input_data.as_bytes())?;
-    drop(stdin);
+        .zip(script_process.stdout.take());
+    let (mut stdin, mut stdout) = pipes.ok_or_else(|| {
+        CgiError::Io(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "Error getting the pipes of the child process",
+        ))
+    })?;
+    let input_data = input_data.as_bytes().to_vec();
+
+    // The program is waited for on another thread, so that it can be killed
+    // if it doesn't complete in time. That thread doesn't reap the program,
+    // so that its process group can't be reused until it is killed.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // Programs may exit without reading the request body
+        let result = match stdin.write_all(&input_data) {
+            Err(error) if error.kind() != io::ErrorKind::BrokenPipe => Err(error),
+            _ => {
+                drop(stdin);
+                let mut output = vec![];
+                stdout
+                    .read_to_end(&mut output)
+                    .and_then(|_| wait_for_exit(pid))
+                    .map(|_| output)
+            }
+        };
+        let _ = sender.send(result);
+    });
+
+    let result = match options.timeout {
+        None => receiver
+            .recv()
+            .map_err(io::Error::other)
+            .and_then(|result| result),
+        Some(timeout) => match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(_) => {
+                debug!("Killing CGI program {pid}");
+                // SAFETY: kill has no memory safety requirements
+                unsafe {
+                    libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+                }
+                // The output of the processes which left the process group
+                // is not waited for
+                drop(running);
+                let _ = script_process.wait();
+                return Err(CgiError::TimedOut(timeout));
+            }
+        },
+    };
+    drop(running);
+    script_process.wait().map_err(CgiError::Io)?;
+    let output = result.map_err(CgiError::Io)?;
 
-    let output_handle = script_process.wait_with_output()?;
-    let output = String::from_utf8_lossy(&output_handle.stdout);
+    Ok(String::from_utf8_lossy(&output).to_string())
+}
 
-    Ok(output.to_string())
+/// Waits for a child process to exit, without reaping it
+///
+fn wait_for_exit(pid: u32) -> io::Result<()> {
+    loop {
+        // SAFETY: siginfo_t is a plain C struct, for which zeroes are valid
+        let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+        // SAFETY: the pointer refers to a valid siginfo_t
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if result == 0 {
+            return Ok(());
+        }
+
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
 }
 
 impl CgiRequestHandler {
     /// Creates a CGI metavariable map to be sent to the CGI program via
     /// environment variables. The data in the map is extracted from the
-    /// incoming TCP stream an HTTP request.
+    /// client connection and the HTTP request.
     ///
     fn generate_environment_variables(
         &self,
-        stream: &TcpStream,
+        connection: &ConnectionInfo,
         request: &Request<String>,
     ) -> CGIMetavariableMap {
         let mut metavariables = CGIMetavariableMap::new();
@@ -124,7 +409,7 @@ impl CgiRequestHandler {
         );
 
         let content_type = get_header_or_empty_string(request, header::CONTENT_TYPE);
-        if content_type != "" {
+        if !content_type.is_empty() {
             metavariables.insert(CGIMetavariable::ContentType, content_type);
         } else {
             metavariables.insert(
@@ -143,9 +428,9 @@ impl CgiRequestHandler {
             request.uri().query().unwrap_or("").to_string(),
         );
 
-        let remote_addr = stream
-            .peer_addr()
-            .map_or(String::from(""), |addr| addr.ip().to_string());
+        let remote_addr = connection
+            .remote_ip()
+            .map_or(String::from(""), |ip| ip.to_string());
         metavariables.insert(CGIMetavariable::RemoteAddr, remote_addr.clone());
         metavariables.insert(CGIMetavariable::RemoteHost, remote_addr);
         metavariables.insert(CGIMetavariable::RemoteIdent, String::from(""));
@@ -157,16 +442,14 @@ impl CgiRequestHandler {
             request.uri().path().to_string(),
         );
 
-        let host_value = request
-            .headers()
-            .get(header::HOST)
-            .map_or(String::from(""), |host| {
-                host.to_str().unwrap_or("").to_string()
-            });
-        let (server_name, server_port) = match host_value.split_once(":") {
-            Some((server_name, server_port)) => (server_name.to_string(), server_port.to_string()),
-            None => (host_value, DEFAULT_PORT.to_string()),
+        let server_name = request_host(request).unwrap_or_default();
+        let default_port = if connection.tls.is_some() {
+            DEFAULT_TLS_PORT
+        } else {
+            DEFAULT_PORT
         };
+        let server_port =
+            request_port(request).map_or(default_port.to_string(), |port| port.to_string());
         metavariables.insert(CGIMetavariable::ServerName, server_name);
         metavariables.insert(CGIMetavariable::ServerPort, server_port);
 
@@ -177,7 +460,11 @@ impl CgiRequestHandler {
             String::from("Rust Web CGI/0.0.1"),
         );
 
-        return metavariables;
+        if connection.tls.is_some() {
+            metavariables.insert(CGIMetavariable::Https, String::from("on"));
+        }
+
+        metavariables
     }
 
     /// Orchestrates the whole execution of the CGI program: sets the
@@ -186,25 +473,51 @@ impl CgiRequestHandler {
     ///
     fn run_cgi_script(
         &self,
-        stream: &TcpStream,
+        connection: &ConnectionInfo,
         request: &Request<String>,
         script_path: PathBuf,
     ) -> Response<String> {
-        let envs = self.generate_environment_variables(stream, request);
+        let envs = self.generate_environment_variables(connection, request);
 
-        match run_process(script_path, request.body(), envs) {
-            Err(_) => generate_error_response(StatusCode::INTERNAL_SERVER_ERROR),
-            Ok(output) => {
+        let response = run_process(script_path.clone(), request.body(), envs, &self.options)
+            .and_then(|output| {
                 debug!("CGI output: {}", output);
-                let cgi_response = parse_cgi_response(output);
+                let cgi_response = parse_cgi_response(output).map_err(CgiError::InvalidResponse)?;
+                convert_cgi_response_to_http(connection, &self.static_handler, cgi_response)
+                    .map_err(CgiError::InvalidResponse)
+            });
 
-                match cgi_response {
-                    Err(_) => generate_error_response(StatusCode::INTERNAL_SERVER_ERROR),
-                    Ok(cgi_response) => {
-                        convert_cgi_response_to_http(stream, &self.static_handler, cgi_response)
-                    }
-                }
+        match response {
+            Ok(response) => response,
+            Err(error) => self.error_response(&script_path, error),
+        }
+    }
+
+    /// Logs the failure of a CGI program and generates the response sent to
+    /// the client, which describes the failure in debug mode
+    ///
+    fn error_response(&self, script_path: &Path, error: CgiError) -> Response<String> {
+        let script = script_path.display();
+        match &error {
+            CgiError::NotExecutable(_) => warn!("CGI program {script} is not executable"),
+            CgiError::InterpreterNotFound(interpreter) => error!(
+                "CGI interpreter {} not found, cannot run {script}",
+                interpreter.display()
+            ),
+            CgiError::Spawn(spawn_error) => {
+                error!("Couldn't start CGI program {script}: {spawn_error}")
             }
+            CgiError::Io(io_error) => error!("Couldn't run CGI program {script}: {io_error}"),
+            CgiError::TimedOut(_) => warn!("CGI program {script} timed out, killed it"),
+            CgiError::InvalidResponse(response_error) => {
+                error!("CGI program {script} sent a malformed response: {response_error}")
+            }
+        }
+
+        if self.options.debug {
+            generate_detailed_error_response(error.status(), &error.to_string())
+        } else {
+            generate_error_response(error.status())
         }
     }
 }
@@ -222,13 +535,21 @@ impl RequestHandler<String> for CgiRequestHandler {
     ///
     fn handle_request(
         &self,
-        stream: &TcpStream,
+        connection: &ConnectionInfo,
         request: &Request<String>,
     ) -> Option<Response<String>> {
         let uri_path = request.uri().path();
         let file_path = &uri_path[1..].strip_prefix(&self.cgi_path)?;
         let file_path = file_path.strip_prefix("/").unwrap_or(file_path);
 
+        let allowed_methods = self.allowed_methods();
+        if request.method() == Method::OPTIONS {
+            return Some(generate_options_response(&allowed_methods));
+        }
+        if !allowed_methods.contains(request.method()) {
+            return Some(generate_method_not_allowed_response(&allowed_methods));
+        }
+
         debug!("CGI script to be loaded: {}", file_path);
         let folder_path = fs::canonicalize(&self.cgi_folder).expect("CGI folder does not exist");
         let file_path = fs::canonicalize(Path::new(&self.cgi_folder).join(file_path));
@@ -247,6 +568,160 @@ impl RequestHandler<String> for CgiRequestHandler {
         };
 
         debug!("Searching for {:?}", abs_file_path);
-        Some(self.run_cgi_script(stream, request, abs_file_path))
+        Some(self.run_cgi_script(connection, request, abs_file_path))
+    }
+
+    fn allowed_methods(&self) -> Vec<Method> {
+        vec![Method::GET, Method::HEAD, Method::POST, Method::OPTIONS]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{process, time::Instant};
+
+    /// Creates a folder containing the given CGI programs, by name
+    ///
+    fn program_folder(test: &str, programs: &[(&str, &str)]) -> PathBuf {
+        let folder = env::temp_dir().join(format!("rust-web-cgi-{}-{test}", process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        for (name, contents) in programs {
+            fs::write(folder.join(name), contents).unwrap();
+        }
+
+        folder
+    }
+
+    /// Requests a CGI program of the given folder
+    ///
+    fn run_program(folder: &Path, program: &str, options: CgiOptions) -> Response<String> {
+        let handler = CgiRequestHandler::new(
+            String::from("cgi"),
+            folder.to_string_lossy().to_string(),
+            StaticRequestHandler::new(String::new(), String::from("public_html")),
+            options,
+        );
+        let request = Request::builder()
+            .uri(format!("/cgi/{program}"))
+            .body(String::new())
+            .unwrap();
+
+        handler
+            .handle_request(&ConnectionInfo::default(), &request)
+            .unwrap()
+    }
+
+    /// Options running the programs with `sh`, so that the test programs
+    /// don't need to be executable
+    ///
+    fn sh_options() -> CgiOptions {
+        CgiOptions {
+            interpreter: Some(PathBuf::from("/bin/sh")),
+            ..CgiOptions::default()
+        }
+    }
+
+    #[test]
+    fn programs_get_the_mount_environment() {
+        let folder = program_folder(
+            "env",
+            &[(
+                "env.sh",
+                "printf 'Content-Type: text/plain\\n\\n%s %s' \"$APP_ENV\" \"$REQUEST_METHOD\"",
+            )],
+        );
+        let options = CgiOptions {
+            env: HashMap::from([
+                (String::from("APP_ENV"), String::from("test")),
+                (String::from("REQUEST_METHOD"), String::from("overridden")),
+            ]),
+            ..sh_options()
+        };
+
+        let response = run_program(&folder, "env.sh", options);
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "test GET");
+    }
+
+    #[test]
+    fn interpreters_run_non_executable_programs() {
+        let folder = program_folder(
+            "interpreter",
+            &[("page.txt", "printf 'Content-Type: text/plain\\n\\nfrom sh'")],
+        );
+
+        let forbidden = run_program(&folder, "page.txt", CgiOptions::default());
+        let response = run_program(&folder, "page.txt", sh_options());
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(forbidden.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "from sh");
+    }
+
+    #[test]
+    fn timed_out_programs_are_killed_with_their_processes() {
+        let folder = program_folder(
+            "timeout",
+            // The process in its own session isn't killed, but keeps the
+            // output open for a while
+            &[(
+                "slow.sh",
+                "setsid sleep 5 &\nsleep 30 &\necho $! > sleep.pid\nwait\n",
+            )],
+        );
+        let options = CgiOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..sh_options()
+        };
+
+        let start = Instant::now();
+        let response = run_program(&folder, "slow.sh", options);
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(start.elapsed() < Duration::from_secs(4));
+
+        // The process started by the program is killed as well, though it
+        // may not be reaped yet
+        let pid = fs::read_to_string(folder.join("sleep.pid")).unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+        assert!(stat.map_or(true, |stat| stat.contains(") Z ")));
+    }
+
+    #[test]
+    fn non_executable_programs_are_forbidden() {
+        let result = run_process(
+            PathBuf::from("Cargo.toml"),
+            "",
+            CGIMetavariableMap::new(),
+            &CgiOptions::default(),
+        );
+
+        let error = result.unwrap_err();
+        assert!(matches!(error, CgiError::NotExecutable(_)));
+        assert_eq!(error.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn interpreters_are_chosen_by_extension() {
+        let mut options = CgiOptions {
+            interpreters: HashMap::from([(String::from("py"), PathBuf::from("/usr/bin/python3"))]),
+            ..CgiOptions::default()
+        };
+        assert_eq!(
+            options.interpreter_for(Path::new("cgi-bin/form.py")),
+            Some(&PathBuf::from("/usr/bin/python3"))
+        );
+        assert_eq!(options.interpreter_for(Path::new("cgi-bin/page.sh")), None);
+
+        options.interpreter = Some(PathBuf::from("/bin/sh"));
+        assert_eq!(
+            options.interpreter_for(Path::new("cgi-bin/form.py")),
+            Some(&PathBuf::from("/bin/sh"))
+        );
     }
 }
@@ -9,6 +9,7 @@ pub enum CGIMetavariable {
     ContentLength,
     ContentType,
     GatewayInterface,
+    Https,
     PathInfo,
     PathTranslated,
     QueryString,
@@ -2,7 +2,7 @@ use http::{Request, Response, StatusCode};
 
 use std::{
     collections::HashMap,
-    net::TcpStream,
+    fmt,
     str::{FromStr, Lines},
 };
 
@@ -10,7 +10,7 @@ use log::debug;
 
 use crate::http_server::{
     request::{request::RequestHandler, static_request::static_handler::StaticRequestHandler},
-    response::generate_error_response,
+    transport::ConnectionInfo,
 };
 
 #[derive(strum_macros::EnumString, Eq, Hash, PartialEq, Debug)]
@@ -23,6 +23,50 @@ pub enum CGIResponseHeader {
 
 pub type CGIResponseHeaderMap = HashMap<CGIResponseHeader, String>;
 
+/// Problems found in the output of a CGI program, which doesn't form a valid
+/// CGI response
+///
+#[derive(Debug, PartialEq)]
+pub enum CgiResponseError {
+    /// The output ended before the blank line closing the header block
+    UnterminatedHeaders,
+    /// A header line has no colon
+    InvalidHeader(String),
+    /// The `Status` header doesn't start with a valid three-digit status
+    /// code
+    InvalidStatus(String),
+    /// A document response has no `Content-Type` header
+    MissingContentType,
+    /// The `Content-Type` or `Location` header can't be used in an HTTP
+    /// response
+    InvalidHeaderValue(String),
+    /// The path of a local redirect isn't served by the static handler
+    UnservedLocation(String),
+}
+
+impl fmt::Display for CgiResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CgiResponseError::UnterminatedHeaders => {
+                write!(f, "the output ended before the end of the header block")
+            }
+            CgiResponseError::InvalidHeader(line) => write!(f, "invalid header line {line:?}"),
+            CgiResponseError::InvalidStatus(status) => write!(f, "invalid status {status:?}"),
+            CgiResponseError::MissingContentType => {
+                write!(f, "the document response has no Content-Type header")
+            }
+            CgiResponseError::InvalidHeaderValue(value) => {
+                write!(f, "invalid header value {value:?}")
+            }
+            CgiResponseError::UnservedLocation(location) => {
+                write!(f, "the local redirect location {location:?} isn't served")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CgiResponseError {}
+
 #[derive(Debug, PartialEq)]
 pub struct CGIScriptResponse {
     headers: CGIResponseHeaderMap,
@@ -37,15 +81,14 @@ impl CGIScriptResponse {
 
 /// Extracts the CGI headers returned from the CGI script.
 ///
-fn parse_cgi_headers(cgi_output: &mut Lines) -> Result<CGIResponseHeaderMap, ()> {
+fn parse_cgi_headers(cgi_output: &mut Lines) -> Result<CGIResponseHeaderMap, CgiResponseError> {
     let mut headers = CGIResponseHeaderMap::new();
 
     loop {
         let next_line = if let Some(line_result) = cgi_output.next() {
             line_result
         } else {
-            debug!("Malformed CGI response");
-            return Err(());
+            return Err(CgiResponseError::UnterminatedHeaders);
         };
 
         if next_line.is_empty() {
@@ -54,16 +97,13 @@ fn parse_cgi_headers(cgi_output: &mut Lines) -> Result<CGIResponseHeaderMap, ()>
 
         let split_line = next_line.split_once(":");
         match split_line {
-            None => {
-                debug!("Invalid CGI header");
-                return Err(());
-            }
+            None => return Err(CgiResponseError::InvalidHeader(next_line.to_string())),
             Some((before, after)) => {
                 let header_value = CGIResponseHeader::from_str(before);
 
                 if let Ok(header_key) = header_value {
                     headers.insert(header_key, after.trim().to_string());
-                } else if let Err(_) = header_value {
+                } else if header_value.is_err() {
                     debug!("Couldn't parse header: {:?}", before);
                 }
             }
@@ -75,13 +115,9 @@ fn parse_cgi_headers(cgi_output: &mut Lines) -> Result<CGIResponseHeaderMap, ()>
 
 /// Extracts the CGI response from the CGI script output
 ///
-pub fn parse_cgi_response(cgi_output: String) -> Result<CGIScriptResponse, ()> {
+pub fn parse_cgi_response(cgi_output: String) -> Result<CGIScriptResponse, CgiResponseError> {
     let mut output_lines = cgi_output.lines();
-    let response_headers = parse_cgi_headers(&mut output_lines);
-    let response_headers = match response_headers {
-        Err(_) => return Err(()),
-        Ok(headers) => headers,
-    };
+    let response_headers = parse_cgi_headers(&mut output_lines)?;
 
     let response_body = output_lines.collect::<String>();
     Ok(CGIScriptResponse::new(response_headers, response_body))
@@ -90,88 +126,85 @@ pub fn parse_cgi_response(cgi_output: String) -> Result<CGIScriptResponse, ()> {
 /// Converts a CGI Local Redirect response into the corresponding HTTP response
 ///
 fn local_redirect(
-    stream: &TcpStream,
+    connection: &ConnectionInfo,
     static_handler: &StaticRequestHandler,
     location: &str,
-) -> Response<String> {
+) -> Result<Response<String>, CgiResponseError> {
     let static_request = Request::builder()
         .method("GET")
         .uri(location)
-        .body(String::from(""));
+        .body(String::from(""))
+        .map_err(|_| CgiResponseError::InvalidHeaderValue(location.to_string()))?;
 
-    match static_request {
-        Err(_) => generate_error_response(StatusCode::INTERNAL_SERVER_ERROR),
-        Ok(static_request) => {
-            let response = static_handler.handle_request(stream, &static_request);
-
-            match response {
-                None => generate_error_response(StatusCode::INTERNAL_SERVER_ERROR),
-                Some(response) => response,
-            }
-        }
-    }
+    static_handler
+        .handle_request(connection, &static_request)
+        .ok_or_else(|| CgiResponseError::UnservedLocation(location.to_string()))
 }
 
 /// Converts a CGI Client Redirect response into the corresponding HTTP
 /// response
 ///
-fn client_redirect(location: &str) -> Response<String> {
-    let response = Response::builder()
+fn client_redirect(location: &str) -> Result<Response<String>, CgiResponseError> {
+    Response::builder()
         .status(StatusCode::FOUND)
         .header("location", location)
-        .body(String::from(""));
+        .body(String::from(""))
+        .map_err(|_| CgiResponseError::InvalidHeaderValue(location.to_string()))
+}
 
-    match response {
-        Err(_) => generate_error_response(StatusCode::INTERNAL_SERVER_ERROR),
-        Ok(response) => response,
+/// Parses the value of a `Status` header, made of a three-digit status code
+/// and a reason phrase, which is ignored since responses are sent with the
+/// standard reason phrase of their status code
+///
+fn parse_status(status: &str) -> Result<StatusCode, CgiResponseError> {
+    let code = status.split_whitespace().next().unwrap_or("");
+    if code.len() != 3 {
+        return Err(CgiResponseError::InvalidStatus(status.to_string()));
     }
+
+    StatusCode::from_bytes(code.as_bytes())
+        .map_err(|_| CgiResponseError::InvalidStatus(status.to_string()))
 }
 
 /// Converts a CGI Document response into the corresponding HTTP response
 ///
-fn document_response(headers: CGIResponseHeaderMap, body: String) -> Response<String> {
+fn document_response(
+    headers: CGIResponseHeaderMap,
+    body: String,
+) -> Result<Response<String>, CgiResponseError> {
     let status = match headers.get(&CGIResponseHeader::Status) {
-        None => String::from(StatusCode::OK.as_str()),
-        Some(status) => status.clone(),
-    };
-
-    let status = match StatusCode::from_str(status.as_str()) {
-        Err(_) => return generate_error_response(StatusCode::INTERNAL_SERVER_ERROR),
-        Ok(value) => value,
+        None => StatusCode::OK,
+        Some(status) => parse_status(status)?,
     };
 
     let content_type = match headers.get(&CGIResponseHeader::ContentType) {
-        None => return generate_error_response(StatusCode::INTERNAL_SERVER_ERROR),
+        None => return Err(CgiResponseError::MissingContentType),
         Some(value) => value,
     };
 
-    let response = Response::builder()
+    Response::builder()
         .status(status)
         .header("content-type", content_type)
-        .body(body);
-
-    match response {
-        Err(_) => generate_error_response(StatusCode::INTERNAL_SERVER_ERROR),
-        Ok(response) => response,
-    }
+        .body(body)
+        .map_err(|_| CgiResponseError::InvalidHeaderValue(content_type.clone()))
 }
 
 /// Converts a CGI response into the corresponding HTTP response. The type of
 /// CGI response is inferred by the CGI headers present in the CGI script
-/// output.
+/// output. Returns an error if the headers can't form a valid response.
 ///
 pub fn convert_cgi_response_to_http(
-    stream: &TcpStream,
+    connection: &ConnectionInfo,
     static_handler: &StaticRequestHandler,
     cgi_response: CGIScriptResponse,
-) -> Response<String> {
+) -> Result<Response<String>, CgiResponseError> {
     let response_headers = cgi_response.headers;
     let response_body = cgi_response.body;
 
     if response_headers.contains_key(&CGIResponseHeader::Location) {
         let location = &response_headers[&CGIResponseHeader::Location];
         if location.starts_with("/") {
-            local_redirect(stream, static_handler, location)
+            local_redirect(connection, static_handler, location)
         } else {
             client_redirect(location)
         }
@@ -205,4 +238,61 @@ mod tests {
 
         assert_eq!(cgi_response, expected);
     }
+
+    #[test]
+    fn malformed_cgi_output_is_reported() {
+        let parse = |output: &str| parse_cgi_response(String::from(output));
+
+        assert_eq!(
+            parse("Content-Type: text/html\n"),
+            Err(CgiResponseError::UnterminatedHeaders)
+        );
+        assert_eq!(
+            parse("Hello!\n\n"),
+            Err(CgiResponseError::InvalidHeader(String::from("Hello!")))
+        );
+
+        let document =
+            |output: &str| document_response(parse(output).unwrap().headers, String::new());
+        assert_eq!(
+            document("Status: abc\nContent-Type: text/html\n\n").unwrap_err(),
+            CgiResponseError::InvalidStatus(String::from("abc"))
+        );
+        assert_eq!(
+            document("Status: 404\n\n").unwrap_err(),
+            CgiResponseError::MissingContentType
+        );
+        assert_eq!(
+            document("Status: 4040 Not Found\nContent-Type: text/html\n\n").unwrap_err(),
+            CgiResponseError::InvalidStatus(String::from("4040 Not Found"))
+        );
+    }
+
+    #[test]
+    fn status_reason_phrases_are_ignored() {
+        let headers = CGIResponseHeaderMap::from([
+            (CGIResponseHeader::Status, String::from("404 Not Found")),
+            (CGIResponseHeader::ContentType, String::from("text/html")),
+        ]);
+
+        let response = document_response(headers, String::new()).unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn unserved_local_redirects_are_reported() {
+        let static_handler =
+            StaticRequestHandler::new(String::from("docs"), String::from("public_html"));
+
+        assert_eq!(
+            local_redirect(
+                &ConnectionInfo::default(),
+                &static_handler,
+                "/other/page.html"
+            )
+            .unwrap_err(),
+            CgiResponseError::UnservedLocation(String::from("/other/page.html"))
+        );
+    }
 }
@@ -1,39 +1,167 @@
-use std::{io::prelude::*, net::TcpStream};
+use std::{
+    io,
+    time::{Duration, Instant},
+};
 
-use http::{Request, Response, StatusCode, Version};
+use http::{Method, Request, Response, StatusCode, Version};
 
 use log::debug;
 
-const BUFFER_SIZE: usize = 8 * 1024; // 8KB
+use crate::http_server::transport::{ConnectionInfo, Transport};
+
+pub const DEFAULT_MAX_REQUEST_SIZE: usize = 8 * 1024; // 8KB
+pub const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_BODY_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Limits applied while exchanging requests and responses with clients
+///
+#[derive(Clone, Debug)]
+pub struct RequestLimits {
+    /// Maximum size of a request, including its start line and headers
+    pub max_request_size: usize,
+    /// Time given to clients to send the start line and headers of a request
+    pub header_timeout: Duration,
+    /// Time given to clients to send the body of a request, once its headers
+    /// are received
+    pub body_timeout: Duration,
+    /// Time after which sending a response is given up if the client doesn't
+    /// receive any data
+    pub write_timeout: Duration,
+}
+
+impl Default for RequestLimits {
+    fn default() -> RequestLimits {
+        RequestLimits {
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+            header_timeout: DEFAULT_HEADER_TIMEOUT,
+            body_timeout: DEFAULT_BODY_TIMEOUT,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+        }
+    }
+}
 
 pub trait RequestHandler<T> {
     /// The `handle_request` trait method should return None if the
     /// corresponding handler shouldn't handle the supplied request. Otherwise
     /// it should return the correct response.
     ///
-    fn handle_request(&self, stream: &TcpStream, request: &Request<T>) -> Option<Response<T>>;
+    fn handle_request(
+        &self,
+        connection: &ConnectionInfo,
+        request: &Request<T>,
+    ) -> Option<Response<T>>;
+
+    /// The `allowed_methods` trait method should return the HTTP methods
+    /// supported by the handler. They are listed in the `Allow` header of
+    /// OPTIONS and METHOD NOT ALLOWED responses.
+    ///
+    fn allowed_methods(&self) -> Vec<Method>;
 }
 
-/// The `handle_request` function reads in an HTTP request from the given TCP
+/// The `load_request` function reads in an HTTP request from the given
 /// stream and returns it. If a valid request can't be read, the HTTP status
-/// to be sent back is returned, wrapped into an `Err` instance. Input from the
-/// TCP stream is expected to be UTF-8 encoded data. If this isn't the case,
-/// a BAD REQUEST status code is returned.
+/// to be sent back is returned, wrapped into an `Err` instance. The request
+/// is parsed with `parse_request`.
 ///
-pub fn load_request(mut stream: &TcpStream) -> Result<Request<String>, StatusCode> {
-    let mut buffer = [0; BUFFER_SIZE + 1];
-    if let Err(_) = stream.read(&mut buffer) {
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+/// Data is read until the whole request is received, as announced by its
+/// `Content-Length` header. A REQUEST TIMEOUT status code is returned if the
+/// headers or the body are not received within the timeouts of `limits`, and
+/// a PAYLOAD TOO LARGE status code if the announced length is over the
+/// maximum request size.
+///
+pub fn load_request(
+    stream: &mut impl Transport,
+    limits: &RequestLimits,
+) -> Result<Request<String>, StatusCode> {
+    let mut buffer = vec![];
+    let mut chunk = [0; READ_CHUNK_SIZE];
+    let mut deadline = Instant::now() + limits.header_timeout;
+    let mut headers_received = false;
+
+    while buffer.len() <= limits.max_request_size {
+        match expected_request_length(&buffer) {
+            Some(length) if length > limits.max_request_size => {
+                return Err(StatusCode::PAYLOAD_TOO_LARGE)
+            }
+            Some(length) if buffer.len() >= length => break,
+            Some(_) if !headers_received => {
+                headers_received = true;
+                deadline = Instant::now() + limits.body_timeout;
+            }
+            _ => {}
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || stream.set_read_timeout(Some(remaining)).is_err() {
+            debug!("Timed out reading the request");
+            return Err(StatusCode::REQUEST_TIMEOUT);
+        }
+
+        match stream.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+            Err(error)
+                if error.kind() == io::ErrorKind::WouldBlock
+                    || error.kind() == io::ErrorKind::TimedOut =>
+            {
+                debug!("Timed out reading the request");
+                return Err(StatusCode::REQUEST_TIMEOUT);
+            }
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        }
     }
 
-    let request_string = if let Ok(text) = String::from_utf8(buffer.to_vec()) {
+    parse_request(buffer, limits)
+}
+
+/// Returns the total length of the request starting in the given data, once
+/// its headers have been received: the length of the headers plus the length
+/// announced by the `Content-Length` header, if any. Returns `None` while the
+/// headers are incomplete.
+///
+pub fn expected_request_length(data: &[u8]) -> Option<usize> {
+    let headers_end = data
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|position| position + 4)
+        .or_else(|| {
+            data.windows(2)
+                .position(|window| window == b"\n\n")
+                .map(|position| position + 2)
+        })?;
+
+    let headers = String::from_utf8_lossy(&data[..headers_end]);
+    let content_length = headers
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    Some(headers_end + content_length)
+}
+
+/// The `parse_request` function parses the HTTP request contained in the
+/// given data. If it isn't a valid request, the HTTP status to be sent back
+/// is returned, wrapped into an `Err` instance. The data is expected to be
+/// UTF-8 encoded. If this isn't the case, a BAD REQUEST status code is
+/// returned. Requests larger than the maximum size in `limits` are answered
+/// with a PAYLOAD TOO LARGE status code.
+///
+pub fn parse_request(data: Vec<u8>, limits: &RequestLimits) -> Result<Request<String>, StatusCode> {
+    let request_string = if let Ok(text) = String::from_utf8(data) {
         text.trim_end_matches(char::from(0)).to_owned()
     } else {
         debug!("Error reading UTF-8 from request buffer");
         return Err(StatusCode::BAD_REQUEST);
     };
 
-    if request_string.len() > BUFFER_SIZE {
+    if request_string.len() > limits.max_request_size {
         return Err(StatusCode::PAYLOAD_TOO_LARGE);
     }
 
@@ -92,8 +220,8 @@ pub fn load_request(mut stream: &TcpStream) -> Result<Request<String>, StatusCod
     }
 
     let mut body = String::from("");
-    while let Some(line_entry) = lines_iter.next() {
-        body.push_str(&line_entry);
+    for line_entry in lines_iter {
+        body.push_str(line_entry);
     }
 
     match request.body(body) {
@@ -104,3 +232,19 @@ pub fn load_request(mut stream: &TcpStream) -> Result<Request<String>, StatusCod
         Ok(request) => Ok(request),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expected_length_includes_the_announced_body() {
+        let headers = "POST /form HTTP/1.1\r\nContent-Length: 7\r\n\r\n";
+
+        assert_eq!(expected_request_length(b"POST /form HTTP/1.1\r\n"), None);
+        assert_eq!(
+            expected_request_length(format!("{headers}a=1").as_bytes()),
+            Some(headers.len() + 7)
+        );
+    }
+}
@@ -1,25 +1,74 @@
-use std::{fs, net::TcpStream, path::Path};
+use std::{fs, path::Path};
 
-use http::{Request, Response, StatusCode};
+use http::{Method, Request, Response, StatusCode};
 
 use log::debug;
 
-use crate::http_server::{request::request::RequestHandler, response::generate_error_response};
+use crate::http_server::{
+    request::{
+        cgi_request::cgi_handler::{CgiOptions, CgiRequestHandler},
+        request::RequestHandler,
+    },
+    response::{
+        generate_error_response, generate_method_not_allowed_response, generate_options_response,
+    },
+    transport::ConnectionInfo,
+};
+
+/// Methods supported for static files
+const STATIC_METHODS: [Method; 3] = [Method::GET, Method::HEAD, Method::OPTIONS];
 
 pub struct StaticRequestHandler {
+    /// URL path at which the folder is served, without the leading and
+    /// trailing slashes (empty for the root path)
+    static_path: String,
     static_folder: String,
+    /// Extensions (without the leading dot) of the files run as CGI programs
+    cgi_extensions: Vec<String>,
+    /// Runs the files with one of the `cgi_extensions`
+    cgi_handler: Option<Box<CgiRequestHandler>>,
 }
 
 impl StaticRequestHandler {
-    pub fn new(static_folder: String) -> StaticRequestHandler {
-        StaticRequestHandler { static_folder }
+    pub fn new(static_path: String, static_folder: String) -> StaticRequestHandler {
+        StaticRequestHandler {
+            static_path,
+            static_folder,
+            cgi_extensions: vec![],
+            cgi_handler: None,
+        }
+    }
+
+    /// Runs the files with one of the given extensions as CGI programs with
+    /// the given options, instead of serving their contents, like Apache's
+    /// `AddHandler cgi-script` directive
+    ///
+    pub fn set_cgi_extensions(&mut self, extensions: Vec<String>, options: CgiOptions) {
+        self.cgi_extensions = extensions
+            .iter()
+            .map(|extension| extension.trim_start_matches('.').to_string())
+            .collect();
+        self.cgi_handler = Some(Box::new(CgiRequestHandler::new(
+            self.static_path.clone(),
+            self.static_folder.clone(),
+            StaticRequestHandler::new(self.static_path.clone(), self.static_folder.clone()),
+            options,
+        )));
+    }
+
+    fn is_cgi_program(&self, file_path: &str) -> bool {
+        Path::new(file_path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| self.cgi_extensions.iter().any(|e| e == extension))
     }
 }
 
 impl RequestHandler<String> for StaticRequestHandler {
-    /// Handles an incoming request. Always returns a `Some` variant, since the
-    /// static handler is kind of a fallback handler. If the requested page
-    /// isn't available, it should return a 404 response.
+    /// Handles an incoming request. Returns a `Some` variant for every path
+    /// under `static_path`, since the static handler is kind of a fallback
+    /// handler. If the requested page isn't available, it should return a
+    /// 404 response.
     ///
     /// # Panics
     ///
@@ -31,18 +80,35 @@ impl RequestHandler<String> for StaticRequestHandler {
     ///
     fn handle_request(
         &self,
-        _stream: &TcpStream,
+        connection: &ConnectionInfo,
         request: &Request<String>,
     ) -> Option<Response<String>> {
-        if request.method() != "GET" && request.method() != "HEAD" {
-            return Some(generate_error_response(StatusCode::METHOD_NOT_ALLOWED));
+        let uri_path = request.uri().path();
+        let file_path = uri_path[1..].strip_prefix(&self.static_path)?;
+        if !file_path.is_empty() && !self.static_path.is_empty() && !file_path.starts_with('/') {
+            return None;
         }
+        let file_path = file_path.trim_start_matches('/');
 
-        let uri_path = request.uri().path();
-        let file_path = if uri_path == "/" {
+        if let Some(cgi_handler) = &self.cgi_handler {
+            if self.is_cgi_program(file_path) {
+                debug!("Running {file_path} as a CGI program");
+                return cgi_handler.handle_request(connection, request);
+            }
+        }
+
+        let allowed_methods = STATIC_METHODS.to_vec();
+        if request.method() == Method::OPTIONS {
+            return Some(generate_options_response(&allowed_methods));
+        }
+        if !allowed_methods.contains(request.method()) {
+            return Some(generate_method_not_allowed_response(&allowed_methods));
+        }
+
+        let file_path = if file_path.is_empty() {
             "index.html"
         } else {
-            &uri_path[1..] // Remove leading slash
+            file_path
         };
 
         let static_folder_path =
@@ -82,4 +148,78 @@ impl RequestHandler<String> for StaticRequestHandler {
                 .expect("Error generating success response"),
         )
     }
+
+    /// Returns the methods supported for static files. The CGI programs of
+    /// the folder list their own methods when asked with OPTIONS.
+    ///
+    fn allowed_methods(&self) -> Vec<Method> {
+        STATIC_METHODS.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, path::PathBuf, process};
+
+    use http::header;
+
+    /// Creates a static folder holding a program printing its method, both
+    /// as `program.py` and as `program.txt`
+    ///
+    fn static_folder() -> PathBuf {
+        let folder = env::temp_dir().join(format!("rust-web-static-{}", process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let program = "printf 'Content-Type: text/plain\\n\\n%s' \"$REQUEST_METHOD\"";
+        fs::write(folder.join("program.py"), program).unwrap();
+        fs::write(folder.join("program.txt"), program).unwrap();
+
+        folder
+    }
+
+    fn request(handler: &StaticRequestHandler, method: Method, path: &str) -> Response<String> {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(String::new())
+            .unwrap();
+
+        handler
+            .handle_request(&ConnectionInfo::default(), &request)
+            .unwrap()
+    }
+
+    #[test]
+    fn files_with_cgi_extensions_are_run() {
+        let folder = static_folder();
+        let mut handler =
+            StaticRequestHandler::new(String::new(), folder.to_string_lossy().to_string());
+        // The test program is a shell script, so that it runs without Python
+        let options = CgiOptions {
+            interpreter: Some(PathBuf::from("/bin/sh")),
+            ..CgiOptions::default()
+        };
+        handler.set_cgi_extensions(vec![String::from(".py")], options);
+
+        let program = request(&handler, Method::GET, "/program.py");
+        let posted = request(&handler, Method::POST, "/program.py");
+        let program_options = request(&handler, Method::OPTIONS, "/program.py");
+        let file = request(&handler, Method::GET, "/program.txt");
+        let rejected = request(&handler, Method::POST, "/program.txt");
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(program.status(), StatusCode::OK);
+        assert_eq!(program.body(), "GET");
+        assert_eq!(posted.body(), "POST");
+        assert_eq!(
+            program_options.headers()[header::ALLOW],
+            "GET, HEAD, POST, OPTIONS"
+        );
+        assert_eq!(file.status(), StatusCode::OK);
+        assert!(file.body().starts_with("printf"));
+        assert_eq!(rejected.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(rejected.headers()[header::ALLOW], "GET, HEAD, OPTIONS");
+        assert_eq!(handler.allowed_methods(), STATIC_METHODS.to_vec());
+    }
 }
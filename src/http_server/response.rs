@@ -1,12 +1,149 @@
-use http::{Response, StatusCode};
+use http::{header, Method, Response, StatusCode};
 
-/// Generates an empty HTTP response with a given status code
+/// Marker stored in the extensions of responses built by
+/// `generate_error_response`. It allows the connection handler to tell
+/// generated error responses apart from error responses carrying their own
+/// document (e.g. the output of a CGI program), and to replace the former
+/// with a configured error page.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct GeneratedErrorResponse;
+
+/// Generates the built-in HTML error page for a given status code
+///
+pub fn default_error_page(status_code: StatusCode) -> String {
+    let status_value = status_code.as_str();
+    let reason = status_code.canonical_reason().unwrap_or("");
+
+    format!(
+        "<html>\n<head><title>{status_value} {reason}</title></head>\n\
+        <body><h1>{status_value} {reason}</h1></body>\n</html>\n"
+    )
+}
+
+/// Generates an HTTP response with a given status code, containing the
+/// built-in error page as its body
+///
+/// # Panics
+///
+/// The `generate_error_response` function panics if the response can't be
+/// built, which shouldn't happen since its headers are hard-coded.
 ///
 pub fn generate_error_response(status_code: StatusCode) -> Response<String> {
-    let mut response = Response::new(String::from(""));
-    *response.status_mut() = status_code;
+    let body = default_error_page(status_code);
+
+    let mut response = Response::builder()
+        .status(status_code)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CONTENT_LENGTH, body.len())
+        .body(body)
+        .expect("Error generating error response");
+    response.extensions_mut().insert(GeneratedErrorResponse);
 
-    return response;
+    response
+}
+
+/// Generates an HTTP response with a given status code, containing the
+/// built-in error page followed by the given details. Unlike the responses
+/// of `generate_error_response`, it isn't replaced by a configured error
+/// page, so that the details reach the client.
+///
+/// # Panics
+///
+/// The `generate_detailed_error_response` function panics if the response
+/// can't be built, which shouldn't happen since its headers are hard-coded.
+///
+pub fn generate_detailed_error_response(
+    status_code: StatusCode,
+    details: &str,
+) -> Response<String> {
+    let details = details
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    let body = default_error_page(status_code).replacen(
+        "</h1>",
+        &format!("</h1>\n<pre>{details}</pre>"),
+        1,
+    );
+
+    Response::builder()
+        .status(status_code)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CONTENT_LENGTH, body.len())
+        .body(body)
+        .expect("Error generating error response")
+}
+
+/// Builds the value of an `Allow` header listing the given methods
+///
+pub fn allow_header_value(methods: &[Method]) -> String {
+    methods
+        .iter()
+        .map(|method| method.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+/// Generates a METHOD NOT ALLOWED response listing the methods which are
+/// supported for the requested resource
+///
+/// # Panics
+///
+/// The `generate_method_not_allowed_response` function panics if one of the
+/// supplied methods can't be used as a header value.
+///
+pub fn generate_method_not_allowed_response(allowed_methods: &[Method]) -> Response<String> {
+    let mut response = generate_error_response(StatusCode::METHOD_NOT_ALLOWED);
+    response.headers_mut().insert(
+        header::ALLOW,
+        allow_header_value(allowed_methods)
+            .parse()
+            .expect("Invalid Allow header"),
+    );
+
+    response
+}
+
+/// Generates a SERVICE UNAVAILABLE response asking the client to retry after
+/// the given number of seconds
+///
+pub fn generate_service_unavailable_response(retry_after: u64) -> Response<String> {
+    let mut response = generate_error_response(StatusCode::SERVICE_UNAVAILABLE);
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, retry_after.into());
+
+    response
+}
+
+/// Generates a TOO MANY REQUESTS response asking the client to retry after
+/// the given number of seconds
+///
+pub fn generate_too_many_requests_response(retry_after: u64) -> Response<String> {
+    let mut response = generate_error_response(StatusCode::TOO_MANY_REQUESTS);
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, retry_after.into());
+
+    response
+}
+
+/// Generates the response to an OPTIONS request, listing the methods which
+/// are supported for the requested resource
+///
+/// # Panics
+///
+/// The `generate_options_response` function panics if one of the supplied
+/// methods can't be used as a header value.
+///
+pub fn generate_options_response(allowed_methods: &[Method]) -> Response<String> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::ALLOW, allow_header_value(allowed_methods))
+        .header(header::CONTENT_LENGTH, 0)
+        .body(String::from(""))
+        .expect("Error generating OPTIONS response")
 }
 
 /// Converts a structured HTTP response into the text data to be sent back to
@@ -34,3 +171,38 @@ pub fn response_to_string(response: Response<String>) -> String {
 
     format!("{status_line}\r\n{header_line}\r\n{contents}")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_response_contains_default_page() {
+        let response = generate_error_response(StatusCode::NOT_FOUND);
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(response.body().contains("<h1>404 Not Found</h1>"));
+        assert!(response
+            .extensions()
+            .get::<GeneratedErrorResponse>()
+            .is_some());
+    }
+
+    #[test]
+    fn detailed_error_response_adds_escaped_details_to_default_page() {
+        let response =
+            generate_detailed_error_response(StatusCode::BAD_GATEWAY, "missing <header> & body");
+
+        assert_eq!(
+            response.body(),
+            &default_error_page(StatusCode::BAD_GATEWAY).replace(
+                "</h1>",
+                "</h1>\n<pre>missing &lt;header&gt; &amp; body</pre>"
+            )
+        );
+        assert!(response
+            .extensions()
+            .get::<GeneratedErrorResponse>()
+            .is_none());
+    }
+}
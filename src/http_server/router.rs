@@ -0,0 +1,450 @@
+use std::{cmp::Reverse, mem};
+
+use http::{Method, Request, Response, StatusCode};
+
+use log::debug;
+
+use crate::http_server::{
+    request::request::RequestHandler,
+    response::{
+        generate_error_response, generate_method_not_allowed_response, generate_options_response,
+    },
+    transport::ConnectionInfo,
+};
+
+/// The URL paths a route applies to
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoutePattern {
+    /// A single path
+    Exact(String),
+    /// A path and every path below it, e.g. `/docs` matches `/docs` and
+    /// `/docs/index.html` but not `/docsets`
+    Prefix(String),
+    /// Paths matching a glob, where `*` matches any part of a path segment,
+    /// `**/` matches any number of segments (including none) and `?`
+    /// matches a single character
+    Glob(String),
+}
+
+impl RoutePattern {
+    /// Returns whether the pattern matches the given path
+    ///
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            RoutePattern::Exact(exact) => path == exact,
+            RoutePattern::Prefix(prefix) => path
+                .strip_prefix(prefix.trim_end_matches('/'))
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/')),
+            RoutePattern::Glob(glob) => glob_matches(glob.as_bytes(), path.as_bytes()),
+        }
+    }
+
+    /// Returns the literal URL path below which the pattern matches: the
+    /// prefix of prefix patterns, the folder of exact paths, and the folders
+    /// of globs before their first wildcard. The files served by a route are
+    /// looked up relative to this path, e.g. `/scripts/a/b.py` matched by
+    /// `/scripts/**/*.py` is `a/b.py` in the folder of the route.
+    ///
+    pub fn base_path(&self) -> &str {
+        let (path, folder_end) = match self {
+            RoutePattern::Prefix(prefix) => return prefix.trim_end_matches('/'),
+            RoutePattern::Exact(exact) => (exact, exact.rfind('/')),
+            RoutePattern::Glob(glob) => {
+                let literal = glob
+                    .find(['*', '?'])
+                    .map_or(glob.as_str(), |end| &glob[..end]);
+                (glob, literal.rfind('/'))
+            }
+        };
+
+        &path[..folder_end.unwrap_or(0)]
+    }
+
+    /// Returns the precedence of the pattern: exact paths come first, then
+    /// globs, then prefixes from the longest to the shortest
+    ///
+    fn precedence(&self) -> (u8, Reverse<usize>) {
+        match self {
+            RoutePattern::Exact(_) => (0, Reverse(0)),
+            RoutePattern::Glob(_) => (1, Reverse(0)),
+            RoutePattern::Prefix(prefix) => (2, Reverse(prefix.trim_end_matches('/').len())),
+        }
+    }
+}
+
+/// Part of a glob pattern
+///
+#[derive(Clone, Copy)]
+enum GlobToken {
+    Byte(u8),
+    /// `?`
+    AnyByte,
+    /// `*`
+    AnyInSegment,
+    /// `**` not followed by a slash
+    Any,
+    /// `**/`
+    AnySegments,
+}
+
+fn glob_tokens(pattern: &[u8]) -> Vec<GlobToken> {
+    let mut tokens = vec![];
+    let mut rest = pattern;
+    while let Some(&first) = rest.first() {
+        let (token, length) = match rest {
+            [b'*', b'*', b'/', ..] => (GlobToken::AnySegments, 3),
+            [b'*', b'*', ..] => (GlobToken::Any, 2),
+            [b'*', ..] => (GlobToken::AnyInSegment, 1),
+            [b'?', ..] => (GlobToken::AnyByte, 1),
+            _ => (GlobToken::Byte(first), 1),
+        };
+        tokens.push(token);
+        rest = &rest[length..];
+    }
+
+    tokens
+}
+
+/// Matches a path against a glob pattern, as described in `RoutePattern`.
+/// The path is read backwards once, keeping track of which suffixes of the
+/// pattern match the rest of the path, so that the time taken is
+/// proportional to the length of the pattern times the length of the path.
+///
+fn glob_matches(pattern: &[u8], path: &[u8]) -> bool {
+    let tokens = glob_tokens(pattern);
+    let length = tokens.len();
+    // `matches[t]`: whether `tokens[t..]` matches the rest of the path.
+    // `after_slash[t]`: whether it matches the rest of the path after its
+    // next slash, for `**/` to skip whole segments.
+    let mut matches = vec![false; length + 1];
+    let mut after_slash = vec![false; length + 1];
+    let mut next_matches = vec![false; length + 1];
+    let mut next_after_slash = vec![false; length + 1];
+
+    for position in (0..=path.len()).rev() {
+        let byte = path.get(position).copied();
+        let in_segment = byte.is_some_and(|byte| byte != b'/');
+        mem::swap(&mut matches, &mut next_matches);
+        mem::swap(&mut after_slash, &mut next_after_slash);
+
+        matches[length] = byte.is_none();
+        for t in (0..length).rev() {
+            after_slash[t] = match byte {
+                Some(b'/') => next_matches[t],
+                Some(_) => next_after_slash[t],
+                None => false,
+            };
+            matches[t] = match tokens[t] {
+                GlobToken::Byte(expected) => byte == Some(expected) && next_matches[t + 1],
+                GlobToken::AnyByte => in_segment && next_matches[t + 1],
+                GlobToken::AnyInSegment => matches[t + 1] || (in_segment && next_matches[t]),
+                GlobToken::Any => matches[t + 1] || (byte.is_some() && next_matches[t]),
+                GlobToken::AnySegments => matches[t + 1] || after_slash[t],
+            };
+        }
+    }
+
+    matches[0]
+}
+
+struct Route {
+    pattern: RoutePattern,
+    /// Methods the route is restricted to, or every method if empty
+    methods: Vec<Method>,
+    handler: Box<dyn RequestHandler<String> + Sync + Send>,
+}
+
+/// Maps the URL paths and methods of requests to the request handlers.
+/// Routes are tried in order of precedence (see `RoutePattern`), routes of
+/// the same precedence in the order they were added, and the first handler
+/// returning a response answers the request.
+///
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Adds a route to the given handler, restricted to the given methods
+    /// unless the list is empty. Routes supporting GET also support HEAD, as
+    /// required by RFC 9110.
+    ///
+    pub fn add(
+        &mut self,
+        pattern: RoutePattern,
+        mut methods: Vec<Method>,
+        handler: Box<dyn RequestHandler<String> + Sync + Send>,
+    ) {
+        if let Some(get) = methods.iter().position(|method| method == Method::GET) {
+            if !methods.contains(&Method::HEAD) {
+                methods.insert(get + 1, Method::HEAD);
+            }
+        }
+
+        let index = self
+            .routes
+            .partition_point(|route| route.pattern.precedence() <= pattern.precedence());
+        self.routes.insert(
+            index,
+            Route {
+                pattern,
+                methods,
+                handler,
+            },
+        );
+    }
+
+    /// Returns every method supported by at least one of the routes, in the
+    /// order they are first listed
+    ///
+    pub fn methods(&self) -> Vec<Method> {
+        let mut methods: Vec<Method> = vec![];
+        for route in &self.routes {
+            let route_methods = if route.methods.is_empty() {
+                route.handler.allowed_methods()
+            } else {
+                route.methods.clone()
+            };
+            for method in route_methods {
+                if !methods.contains(&method) {
+                    methods.push(method);
+                }
+            }
+        }
+
+        methods
+    }
+
+    /// Answers a request with the first matching route. Requests matching a
+    /// route restricted to other methods get a METHOD NOT ALLOWED response
+    /// (or the list of those methods for OPTIONS requests), unless a route
+    /// with the same pattern allows their method: the routes of lower
+    /// precedence aren't tried. Requests which are not routed at all get a
+    /// NOT FOUND response.
+    ///
+    pub fn route(
+        &self,
+        connection: &ConnectionInfo,
+        request: &Request<String>,
+    ) -> Response<String> {
+        let path = request.uri().path();
+        let mut other_methods: Vec<Method> = vec![];
+        // Pattern of the first route restricted to other methods
+        let mut restricted_pattern: Option<&RoutePattern> = None;
+
+        for route in self
+            .routes
+            .iter()
+            .filter(|route| route.pattern.matches(path))
+        {
+            if restricted_pattern.is_some_and(|pattern| *pattern != route.pattern) {
+                break;
+            }
+            if !route.methods.is_empty() && !route.methods.contains(request.method()) {
+                for method in &route.methods {
+                    if !other_methods.contains(method) {
+                        other_methods.push(method.clone());
+                    }
+                }
+                restricted_pattern.get_or_insert(&route.pattern);
+                continue;
+            }
+
+            debug!("Request routed to {:?}", route.pattern);
+            if let Some(response) = route.handler.handle_request(connection, request) {
+                return response;
+            }
+        }
+
+        if other_methods.is_empty() {
+            return generate_error_response(StatusCode::NOT_FOUND);
+        }
+        if !other_methods.contains(&Method::OPTIONS) {
+            other_methods.push(Method::OPTIONS);
+        }
+        if request.method() == Method::OPTIONS {
+            generate_options_response(&other_methods)
+        } else {
+            generate_method_not_allowed_response(&other_methods)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::http_server::request::static_request::static_handler::StaticRequestHandler;
+
+    /// Answers every request with a fixed body
+    struct Fixed(&'static str);
+
+    impl RequestHandler<String> for Fixed {
+        fn handle_request(
+            &self,
+            _connection: &ConnectionInfo,
+            _request: &Request<String>,
+        ) -> Option<Response<String>> {
+            Some(Response::new(self.0.to_string()))
+        }
+
+        fn allowed_methods(&self) -> Vec<Method> {
+            vec![Method::GET, Method::POST]
+        }
+    }
+
+    fn routed(router: &Router, method: Method, path: &str) -> Response<String> {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(String::from(""))
+            .unwrap();
+        router.route(&ConnectionInfo::default(), &request)
+    }
+
+    #[test]
+    fn routes_are_tried_in_order_of_precedence() {
+        let mut router = Router::new();
+        router.add(
+            RoutePattern::Prefix(String::from("/")),
+            vec![],
+            Box::new(Fixed("root")),
+        );
+        router.add(
+            RoutePattern::Prefix(String::from("/docs")),
+            vec![],
+            Box::new(Fixed("docs")),
+        );
+        router.add(
+            RoutePattern::Glob(String::from("/docs/**/*.sh")),
+            vec![Method::POST],
+            Box::new(Fixed("scripts")),
+        );
+        router.add(
+            RoutePattern::Exact(String::from("/docs/status")),
+            vec![],
+            Box::new(Fixed("status")),
+        );
+
+        let body = |method, path| routed(&router, method, path).into_body();
+        assert_eq!(body(Method::GET, "/index.html"), "root");
+        assert_eq!(body(Method::GET, "/docsets"), "root");
+        assert_eq!(body(Method::POST, "/docs/a/b.sh"), "scripts");
+        assert_eq!(body(Method::POST, "/docs/b.sh"), "scripts");
+        assert_eq!(
+            routed(&router, Method::GET, "/docs/a/b.sh").status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(body(Method::GET, "/docs/status"), "status");
+    }
+
+    #[test]
+    fn globs_match_paths() {
+        let matches = |glob: &str, path: &str| RoutePattern::Glob(glob.to_string()).matches(path);
+
+        assert!(matches("/*.py", "/a.py"));
+        assert!(!matches("/*.py", "/a/b.py"));
+        assert!(matches("/**/*.py", "/a.py"));
+        assert!(matches("/**/*.py", "/a/b/c.py"));
+        assert!(!matches("/a/**/b", "/a/xb"));
+        assert!(matches("/a/**", "/a/b/c"));
+        assert!(matches("/a?c", "/abc"));
+        assert!(!matches("/a?c", "/a/c"));
+        assert!(!matches("/*.py", "/a.pyc"));
+
+        // Backtracking over every star would take exponential time
+        let path = format!("/{}", "a".repeat(8000));
+        assert!(!matches("/*a*a*a*a*a*a*a*a*b", &path));
+        assert!(!matches("/**a**a**a**a**a**a**b", &path));
+    }
+
+    #[test]
+    fn base_paths_are_the_literal_folders() {
+        let base_path = |pattern: RoutePattern| pattern.base_path().to_string();
+
+        assert_eq!(
+            base_path(RoutePattern::Prefix(String::from("/docs/"))),
+            "/docs"
+        );
+        assert_eq!(base_path(RoutePattern::Exact(String::from("/status"))), "");
+        assert_eq!(
+            base_path(RoutePattern::Exact(String::from("/a/b.sh"))),
+            "/a"
+        );
+        assert_eq!(
+            base_path(RoutePattern::Glob(String::from("/scripts/**/*.py"))),
+            "/scripts"
+        );
+        assert_eq!(
+            base_path(RoutePattern::Glob(String::from("/a/b?/*.py"))),
+            "/a"
+        );
+        assert_eq!(base_path(RoutePattern::Glob(String::from("/*.py"))), "");
+    }
+
+    #[test]
+    fn restricted_routes_reject_other_methods() {
+        let mut router = Router::new();
+        router.add(
+            RoutePattern::Glob(String::from("/api/*")),
+            vec![Method::GET],
+            Box::new(Fixed("api")),
+        );
+
+        assert_eq!(
+            routed(&router, Method::GET, "/api/users").status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            routed(&router, Method::HEAD, "/api/users").status(),
+            StatusCode::OK
+        );
+        let rejected = routed(&router, Method::POST, "/api/users");
+        assert_eq!(rejected.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(rejected.headers()["allow"], "GET, HEAD, OPTIONS");
+        assert_eq!(
+            routed(&router, Method::GET, "/api/users/1").status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn restricted_routes_are_not_handled_by_the_fallback_route() {
+        // The static root route added to every virtual host, serving the
+        // files the restricted routes match
+        let mut router = Router::new();
+        router.add(
+            RoutePattern::Prefix(String::from("/")),
+            vec![],
+            Box::new(StaticRequestHandler::new(
+                String::new(),
+                String::from("public_html"),
+            )),
+        );
+        router.add(
+            RoutePattern::Glob(String::from("/*.html")),
+            vec![Method::POST],
+            Box::new(Fixed("post")),
+        );
+        router.add(
+            RoutePattern::Glob(String::from("/*.html")),
+            vec![Method::PUT],
+            Box::new(Fixed("put")),
+        );
+
+        assert_eq!(routed(&router, Method::POST, "/index.html").body(), "post");
+        assert_eq!(routed(&router, Method::PUT, "/index.html").body(), "put");
+        let rejected = routed(&router, Method::GET, "/index.html");
+        assert_eq!(rejected.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(rejected.headers()["allow"], "POST, PUT, OPTIONS");
+        let options = routed(&router, Method::OPTIONS, "/index.html");
+        assert_eq!(options.status(), StatusCode::OK);
+        assert_eq!(options.headers()["allow"], "POST, PUT, OPTIONS");
+        assert_eq!(routed(&router, Method::GET, "/").status(), StatusCode::OK);
+    }
+}
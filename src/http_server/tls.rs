@@ -0,0 +1,146 @@
+use std::{
+    fmt, fs,
+    io::{self, BufReader},
+    net::TcpStream,
+    path::Path,
+    sync::Arc,
+};
+
+use rustls::{
+    crypto::ring,
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig, ServerConnection, StreamOwned,
+};
+
+use crate::{config::TlsConfig, http_server::virtual_host::HostPattern};
+
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// Error found while loading the certificates and keys of a TLS listener
+///
+#[derive(Debug)]
+pub struct TlsError(String);
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+/// Selects the certificate sent to a client from the host name it requested
+/// through SNI. The default certificate is used for clients not sending SNI
+/// or requesting an unknown host.
+///
+#[derive(Debug)]
+struct SniCertificateResolver {
+    default_key: Arc<CertifiedKey>,
+    keys: Vec<(HostPattern, Arc<CertifiedKey>)>,
+}
+
+impl ResolvesServerCert for SniCertificateResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let mut selected = (0, &self.default_key);
+
+        if let Some(server_name) = client_hello.server_name() {
+            let server_name = server_name.to_ascii_lowercase();
+            for (pattern, key) in &self.keys {
+                if let Some(specificity) = pattern.specificity(&server_name) {
+                    if specificity > selected.0 {
+                        selected = (specificity, key);
+                    }
+                }
+            }
+        }
+
+        Some(Arc::clone(selected.1))
+    }
+}
+
+/// Loads a PEM certificate chain and its private key
+///
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<Arc<CertifiedKey>, TlsError> {
+    let cert_file = fs::File::open(cert_path).map_err(|error| {
+        TlsError(format!(
+            "could not open certificate {}: {error}",
+            cert_path.display()
+        ))
+    })?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|error| {
+            TlsError(format!(
+                "invalid certificate {}: {error}",
+                cert_path.display()
+            ))
+        })?;
+    if certs.is_empty() {
+        return Err(TlsError(format!(
+            "no certificate found in {}",
+            cert_path.display()
+        )));
+    }
+
+    let key_file = fs::File::open(key_path).map_err(|error| {
+        TlsError(format!(
+            "could not open private key {}: {error}",
+            key_path.display()
+        ))
+    })?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key_file))
+        .map_err(|error| {
+            TlsError(format!(
+                "invalid private key {}: {error}",
+                key_path.display()
+            ))
+        })?
+        .ok_or_else(|| TlsError(format!("no private key found in {}", key_path.display())))?;
+
+    let signing_key = ring::sign::any_supported_type(&key).map_err(|error| {
+        TlsError(format!(
+            "unsupported private key {}: {error}",
+            key_path.display()
+        ))
+    })?;
+
+    Ok(Arc::new(CertifiedKey::new(certs, signing_key)))
+}
+
+/// Builds the TLS configuration of a listener, loading its default
+/// certificate and the certificates selected through SNI
+///
+pub fn load_server_config(config: &TlsConfig) -> Result<Arc<ServerConfig>, TlsError> {
+    let default_key = load_certified_key(&config.cert, &config.key)?;
+
+    let mut keys = vec![];
+    for certificate in &config.sni {
+        let key = load_certified_key(&certificate.cert, &certificate.key)?;
+        for name in &certificate.names {
+            let pattern = HostPattern::parse(name).map_err(TlsError)?;
+            keys.push((pattern, Arc::clone(&key)));
+        }
+    }
+
+    let server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|error| TlsError(error.to_string()))?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(SniCertificateResolver { default_key, keys }));
+
+    Ok(Arc::new(server_config))
+}
+
+/// Performs the TLS handshake on an accepted TCP connection, returning the
+/// encrypted stream
+///
+pub fn accept(stream: TcpStream, config: Arc<ServerConfig>) -> io::Result<TlsStream> {
+    let tls_connection = ServerConnection::new(config).map_err(io::Error::other)?;
+    let mut tls_stream = StreamOwned::new(tls_connection, stream);
+    while tls_stream.conn.is_handshaking() {
+        tls_stream.conn.complete_io(&mut tls_stream.sock)?;
+    }
+
+    Ok(tls_stream)
+}
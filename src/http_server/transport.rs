@@ -0,0 +1,196 @@
+use std::{
+    io::{self, Cursor, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    time::Duration,
+};
+
+use crate::http_server::tls::TlsStream;
+
+/// Details of a TLS session
+///
+#[derive(Clone, Debug, Default)]
+pub struct TlsInfo {
+    /// Host name sent by the client through SNI, if any
+    pub server_name: Option<String>,
+}
+
+/// Details of the client connection a request was received from, made
+/// available to the request handlers
+///
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionInfo {
+    pub peer_addr: Option<SocketAddr>,
+    pub local_addr: Option<SocketAddr>,
+    /// Set if the connection is encrypted with TLS
+    pub tls: Option<TlsInfo>,
+    /// Path of the Unix domain socket the connection was accepted on, if any
+    pub unix_socket: Option<PathBuf>,
+}
+
+impl ConnectionInfo {
+    /// Returns the IP address of the client. IPv4 clients of dual-stack
+    /// listeners get their IPv4 address instead of an IPv4-mapped IPv6
+    /// address. Clients connected through a Unix domain socket are local
+    /// processes (usually a reverse proxy), so the loopback address is
+    /// returned for them.
+    ///
+    pub fn remote_ip(&self) -> Option<IpAddr> {
+        match (self.peer_addr, &self.unix_socket) {
+            (Some(peer_addr), _) => Some(peer_addr.ip().to_canonical()),
+            (None, Some(_)) => Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// A bidirectional stream connected to a client, such as a TCP or TLS
+/// stream. Requests are read from it and responses written back to it, and
+/// it describes the connection to the request handlers.
+///
+pub trait Transport: Read + Write {
+    fn connection_info(&self) -> ConnectionInfo;
+
+    /// Sets the time after which reading from the transport fails if no data
+    /// arrives. Reads never time out if `None` is given.
+    ///
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Sets the time after which writing to the transport fails if the peer
+    /// doesn't receive any data. Writes never time out if `None` is given.
+    ///
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    fn connection_info(&self) -> ConnectionInfo {
+        (**self).connection_info()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_write_timeout(timeout)
+    }
+}
+
+impl Transport for TcpStream {
+    fn connection_info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            peer_addr: self.peer_addr().ok(),
+            local_addr: self.local_addr().ok(),
+            tls: None,
+            unix_socket: None,
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+impl Transport for UnixStream {
+    fn connection_info(&self) -> ConnectionInfo {
+        let unix_socket = self
+            .local_addr()
+            .ok()
+            .and_then(|address| address.as_pathname().map(PathBuf::from))
+            .unwrap_or_default();
+
+        ConnectionInfo {
+            unix_socket: Some(unix_socket),
+            ..ConnectionInfo::default()
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+}
+
+impl Transport for TlsStream {
+    fn connection_info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            tls: Some(TlsInfo {
+                server_name: self.conn.server_name().map(String::from),
+            }),
+            ..self.sock.connection_info()
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_write_timeout(timeout)
+    }
+}
+
+/// An in-memory transport, reading the request from a buffer and storing the
+/// response written back. Mostly useful for testing request handling without
+/// opening sockets.
+///
+#[derive(Debug)]
+pub struct MemoryTransport {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+    connection: ConnectionInfo,
+}
+
+impl MemoryTransport {
+    pub fn new(input: &[u8], connection: ConnectionInfo) -> MemoryTransport {
+        MemoryTransport {
+            input: Cursor::new(input.to_vec()),
+            output: vec![],
+            connection,
+        }
+    }
+
+    /// Returns the data written to the transport
+    ///
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+impl Read for MemoryTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn connection_info(&self) -> ConnectionInfo {
+        self.connection.clone()
+    }
+
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,359 @@
+use http::{header, Method, Request, Response, StatusCode};
+
+use log::{debug, warn};
+
+use crate::http_server::{
+    error_pages::ErrorPages,
+    response::{generate_error_response, generate_options_response, GeneratedErrorResponse},
+    router::Router,
+    transport::ConnectionInfo,
+};
+
+/// Methods defined by the HTTP specification. Requests using any other method
+/// are answered with a NOT IMPLEMENTED response.
+const KNOWN_METHODS: [Method; 9] = [
+    Method::GET,
+    Method::HEAD,
+    Method::POST,
+    Method::PUT,
+    Method::DELETE,
+    Method::CONNECT,
+    Method::OPTIONS,
+    Method::TRACE,
+    Method::PATCH,
+];
+
+/// Returns the host name a request is directed to, without the port and in
+/// lowercase. The authority of an absolute-form request URI takes precedence
+/// over the `Host` header, as required by RFC 7230 (section 5.4).
+///
+pub fn request_host(request: &Request<String>) -> Option<String> {
+    let host = match request.uri().host() {
+        Some(host) => host.to_string(),
+        None => {
+            let host_value = request.headers().get(header::HOST)?.to_str().ok()?;
+            strip_port(host_value.trim()).to_string()
+        }
+    };
+
+    if host.is_empty() {
+        None
+    } else {
+        Some(host.to_ascii_lowercase())
+    }
+}
+
+/// Returns the port a request is directed to, read from the absolute-form
+/// request URI or the `Host` header
+///
+pub fn request_port(request: &Request<String>) -> Option<u16> {
+    if let Some(port) = request.uri().port_u16() {
+        return Some(port);
+    }
+
+    let host_value = request.headers().get(header::HOST)?.to_str().ok()?.trim();
+    let port = &host_value[strip_port(host_value).len()..];
+    port.strip_prefix(':')?.parse().ok()
+}
+
+/// Removes the port from a `Host` header value, taking IPv6 literals (such as
+/// `[::1]:8080`) into account
+///
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host.find(']').map_or(host, |end| &host[..=end]);
+    }
+
+    host.split_once(':').map_or(host, |(name, _)| name)
+}
+
+/// A host name pattern identifying a virtual host. Patterns can be exact
+/// names (`example.com`), wildcards matching any subdomain (`*.example.com`)
+/// or a single `*`, matching every host.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum HostPattern {
+    Exact(String),
+    Wildcard(String),
+    Any,
+}
+
+impl HostPattern {
+    /// Parses a host name pattern, returning a description of the problem if
+    /// the pattern is invalid.
+    ///
+    pub fn parse(pattern: &str) -> Result<HostPattern, String> {
+        let pattern = pattern.trim().to_ascii_lowercase();
+
+        if pattern == "*" {
+            return Ok(HostPattern::Any);
+        }
+
+        let (name, is_wildcard) = match pattern.strip_prefix("*.") {
+            Some(suffix) => (suffix, true),
+            None => (pattern.as_str(), false),
+        };
+
+        let is_ipv6_literal = name.starts_with('[') && name.ends_with(']');
+        if name.is_empty()
+            || name.contains(['*', '/', ' '])
+            || (name.contains(':') && !is_ipv6_literal)
+        {
+            return Err(format!("invalid host name pattern {pattern}"));
+        }
+
+        if is_wildcard {
+            Ok(HostPattern::Wildcard(format!(".{name}")))
+        } else {
+            Ok(HostPattern::Exact(name.to_string()))
+        }
+    }
+
+    /// Returns how specific the match of this pattern against the given host
+    /// is, or `None` if it doesn't match. Exact names are the most specific,
+    /// followed by wildcards with the longest suffix.
+    ///
+    pub fn specificity(&self, host: &str) -> Option<usize> {
+        match self {
+            HostPattern::Exact(name) => (name == host).then_some(usize::MAX),
+            HostPattern::Wildcard(suffix) => host
+                .strip_suffix(suffix.as_str())
+                .filter(|subdomain| !subdomain.is_empty())
+                .map(|_| suffix.len()),
+            HostPattern::Any => Some(0),
+        }
+    }
+}
+
+/// A site served by the server: the request handlers and error documents
+/// used for the requests directed to one or more host names.
+///
+pub struct VirtualHost {
+    router: Router,
+    error_pages: ErrorPages,
+}
+
+impl VirtualHost {
+    pub fn new(router: Router, error_pages: ErrorPages) -> VirtualHost {
+        VirtualHost {
+            router,
+            error_pages,
+        }
+    }
+
+    /// Requests are handed to the handlers of the virtual host by its
+    /// router. Requests with unknown methods and `OPTIONS *` requests, which
+    /// target the server as a whole, are answered without consulting the
+    /// handlers.
+    ///
+    pub fn dispatch(
+        &self,
+        request: &Request<String>,
+        connection: &ConnectionInfo,
+    ) -> Response<String> {
+        if !KNOWN_METHODS.contains(request.method()) {
+            debug!("Unknown method: {}", request.method());
+            return generate_error_response(StatusCode::NOT_IMPLEMENTED);
+        }
+
+        if request.method() == Method::OPTIONS && request.uri() == "*" {
+            return generate_options_response(&self.router.methods());
+        }
+
+        self.router.route(connection, request)
+    }
+
+    /// Replaces the body of a generated error response with the error
+    /// document configured for its status, if any. The document is fetched
+    /// through an internal GET request, so both static files and CGI scripts
+    /// can be used. The original status and headers are kept, and the
+    /// response is returned untouched if the document can't be loaded.
+    ///
+    pub fn apply_error_page(
+        &self,
+        response: Response<String>,
+        connection: &ConnectionInfo,
+    ) -> Response<String> {
+        if response
+            .extensions()
+            .get::<GeneratedErrorResponse>()
+            .is_none()
+        {
+            return response;
+        }
+
+        let location = match self.error_pages.get(response.status()) {
+            None => return response,
+            Some(location) => location,
+        };
+
+        let document_request = Request::builder()
+            .method("GET")
+            .uri(location)
+            .body(String::from(""));
+        let document_request = match document_request {
+            Err(_) => {
+                warn!("Invalid error document location: {location}");
+                return response;
+            }
+            Ok(request) => request,
+        };
+
+        let document = self.dispatch(&document_request, connection);
+        if !document.status().is_success() {
+            warn!(
+                "Error document {location} could not be loaded ({})",
+                document.status()
+            );
+            return response;
+        }
+
+        let (mut parts, _) = response.into_parts();
+        let (document_parts, document_body) = document.into_parts();
+
+        if let Some(content_type) = document_parts.headers.get(header::CONTENT_TYPE) {
+            parts
+                .headers
+                .insert(header::CONTENT_TYPE, content_type.clone());
+        }
+        parts
+            .headers
+            .insert(header::CONTENT_LENGTH, document_body.len().into());
+
+        Response::from_parts(parts, document_body)
+    }
+}
+
+/// The set of virtual hosts served by the server. Requests are directed to
+/// the virtual host whose name pattern matches the requested host most
+/// specifically, or to the default virtual host if none matches.
+///
+pub struct VirtualHosts {
+    hosts: Vec<(Vec<HostPattern>, VirtualHost)>,
+    default_host: VirtualHost,
+}
+
+impl VirtualHosts {
+    pub fn new(default_host: VirtualHost) -> VirtualHosts {
+        VirtualHosts {
+            hosts: vec![],
+            default_host,
+        }
+    }
+
+    /// Adds a virtual host serving the requests directed to any of the given
+    /// name patterns
+    ///
+    pub fn add(&mut self, patterns: Vec<HostPattern>, host: VirtualHost) {
+        self.hosts.push((patterns, host));
+    }
+
+    /// Returns the default virtual host, used when the requested host is
+    /// unknown or can't be determined
+    ///
+    pub fn default_host(&self) -> &VirtualHost {
+        &self.default_host
+    }
+
+    /// Returns the virtual host serving the given host name
+    ///
+    pub fn select(&self, host: Option<&str>) -> &VirtualHost {
+        let host = match host {
+            None => return &self.default_host,
+            Some(host) => host,
+        };
+
+        let mut selected = None;
+        for (patterns, virtual_host) in &self.hosts {
+            for pattern in patterns {
+                if let Some(specificity) = pattern.specificity(host) {
+                    if selected.is_none_or(|(best, _)| specificity > best) {
+                        selected = Some((specificity, virtual_host));
+                    }
+                }
+            }
+        }
+
+        selected.map_or(&self.default_host, |(_, virtual_host)| virtual_host)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(names: &[&str]) -> Vec<HostPattern> {
+        names
+            .iter()
+            .map(|name| HostPattern::parse(name).unwrap())
+            .collect()
+    }
+
+    fn host_with_error_page(location: &str) -> VirtualHost {
+        VirtualHost::new(
+            Router::new(),
+            ErrorPages::from([(StatusCode::NOT_FOUND, location.to_string())]),
+        )
+    }
+
+    fn selected_page(hosts: &VirtualHosts, host: Option<&str>) -> String {
+        hosts
+            .select(host)
+            .error_pages
+            .get(StatusCode::NOT_FOUND)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn host_is_read_from_header_or_absolute_uri() {
+        let request = Request::builder()
+            .uri("/index.html")
+            .header("Host", "Example.com:8080")
+            .body(String::from(""))
+            .unwrap();
+        assert_eq!(request_host(&request), Some(String::from("example.com")));
+
+        let request = Request::builder()
+            .uri("http://other.org/index.html")
+            .header("Host", "example.com")
+            .body(String::from(""))
+            .unwrap();
+        assert_eq!(request_host(&request), Some(String::from("other.org")));
+
+        let request = Request::builder()
+            .uri("/")
+            .header("Host", "[::1]:8080")
+            .body(String::from(""))
+            .unwrap();
+        assert_eq!(request_host(&request), Some(String::from("[::1]")));
+    }
+
+    #[test]
+    fn most_specific_host_is_selected() {
+        let mut hosts = VirtualHosts::new(host_with_error_page("/default"));
+        hosts.add(
+            patterns(&["*.example.com"]),
+            host_with_error_page("/wildcard"),
+        );
+        hosts.add(
+            patterns(&["*.api.example.com"]),
+            host_with_error_page("/api"),
+        );
+        hosts.add(patterns(&["www.example.com"]), host_with_error_page("/www"));
+
+        assert_eq!(selected_page(&hosts, Some("www.example.com")), "/www");
+        assert_eq!(selected_page(&hosts, Some("a.example.com")), "/wildcard");
+        assert_eq!(selected_page(&hosts, Some("v1.api.example.com")), "/api");
+        assert_eq!(selected_page(&hosts, Some("example.com")), "/default");
+        assert_eq!(selected_page(&hosts, None), "/default");
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        assert!(HostPattern::parse("").is_err());
+        assert!(HostPattern::parse("www.*.com").is_err());
+        assert!(HostPattern::parse("example.com:80").is_err());
+        assert_eq!(HostPattern::parse("*"), Ok(HostPattern::Any));
+    }
+}
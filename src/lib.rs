@@ -1,2 +1,3 @@
+pub mod config;
 pub mod http_server;
 pub mod threadpool;
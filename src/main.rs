@@ -1,47 +1,512 @@
-use std::net::TcpListener;
-use std::sync::Arc;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command};
+use std::sync::{atomic::AtomicBool, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
 
+use clap::Parser;
+
+use signal_hook::{
+    consts::{SIGCHLD, SIGHUP, SIGINT, SIGTERM, SIGUSR2},
+    iterator::Signals,
+};
+
+use rust_web_cgi::config::{CgiMountConfig, Config, ConfigError, RouteConfig};
 use rust_web_cgi::http_server::{
     connection::ConnectionHandler,
+    connection_limits::ConnectionLimiter,
+    error_pages::ErrorPages,
+    listener::{self, InheritedListeners, Listener, ServedListener, ShutdownNotifier},
+    middleware::{AccessLog, Middleware, MiddlewareChain, ResponseHeaders},
+    rate_limit::RateLimiter,
     request::{
-        cgi_request::cgi_handler::CgiRequestHandler,
+        cgi_request::cgi_handler::{self, CgiOptions, CgiRequestHandler},
+        request::RequestHandler,
         static_request::static_handler::StaticRequestHandler,
     },
+    router::{RoutePattern, Router},
+    tls,
+    virtual_host::{HostPattern, VirtualHost, VirtualHosts},
 };
 use rust_web_cgi::threadpool::ThreadPool;
 
-const ADDR_AND_PORT: &str = "127.0.0.1:8080";
-const POOL_SIZE: usize = 4;
+#[cfg(feature = "event-loop")]
+use rust_web_cgi::http_server::event_loop::EventLoop;
+
+use cli::Cli;
+
+mod cli;
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Loads the configuration file and applies the command-line overrides and
+/// flags on top of it. Without a `--config` argument, the default
+/// configuration file is used if it exists.
+///
+fn load_config(cli: &Cli) -> Result<Config, ConfigError> {
+    let config_path = cli
+        .config
+        .clone()
+        .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.is_file()));
+
+    let mut config = Config::load(config_path.as_deref(), &cli.overrides)?;
+    cli.apply(&mut config);
+    config.validate()?;
+
+    Ok(config)
+}
+
+/// Creates a virtual host serving the static files of `root` (running the
+/// ones with one of `cgi_extensions` as CGI programs), the given CGI mounts
+/// and the given additional routes. CGI programs are run with the given
+/// default settings, such as the interpreters by extension.
+///
+fn build_virtual_host(
+    root: &Path,
+    cgi_extensions: &[String],
+    cgi_mounts: &[CgiMountConfig],
+    routes: &[RouteConfig],
+    error_pages: ErrorPages,
+    cgi_options: &CgiOptions,
+) -> VirtualHost {
+    let static_folder = root.to_string_lossy().to_string();
+    let mut router = Router::new();
+
+    for route in routes {
+        let Some(pattern) = route.pattern() else {
+            continue;
+        };
+        // Routes serve their folder at the literal part of their pattern
+        let mount_path = pattern.base_path().trim_matches('/').to_string();
+        let handler: Box<dyn RequestHandler<String> + Sync + Send> =
+            match (&route.static_folder, &route.cgi) {
+                (Some(folder), _) => Box::new(StaticRequestHandler::new(
+                    mount_path,
+                    folder.to_string_lossy().to_string(),
+                )),
+                (None, Some(folder)) => Box::new(CgiRequestHandler::new(
+                    mount_path,
+                    folder.to_string_lossy().to_string(),
+                    StaticRequestHandler::new(String::new(), static_folder.clone()),
+                    route
+                        .cgi_mount()
+                        .map_or(cgi_options.clone(), |mount| mount.options(cgi_options)),
+                )),
+                (None, None) => continue,
+            };
+        router.add(pattern, route.methods(), handler);
+    }
+
+    for mount in cgi_mounts {
+        router.add(
+            RoutePattern::Prefix(format!("/{}", mount.path.trim_matches('/'))),
+            vec![],
+            Box::new(CgiRequestHandler::new(
+                mount.path.trim_matches('/').to_string(),
+                mount.folder.to_string_lossy().to_string(),
+                StaticRequestHandler::new(String::new(), static_folder.clone()),
+                mount.options(cgi_options),
+            )),
+        );
+    }
+    let mut static_handler = StaticRequestHandler::new(String::new(), static_folder);
+    if !cgi_extensions.is_empty() {
+        static_handler.set_cgi_extensions(cgi_extensions.to_vec(), cgi_options.clone());
+    }
+    router.add(
+        RoutePattern::Prefix(String::from("/")),
+        vec![],
+        Box::new(static_handler),
+    );
+
+    VirtualHost::new(router, error_pages)
+}
+
+/// Creates the middlewares listed in the configuration, in order
+///
+/// # Panics
+///
+/// The `build_middlewares` function panics if a middleware name or a
+/// response header is invalid, which is checked beforehand when validating
+/// the configuration.
+///
+fn build_middlewares(config: &Config) -> MiddlewareChain {
+    config
+        .server
+        .middleware
+        .iter()
+        .map(|name| -> Arc<dyn Middleware + Sync + Send> {
+            match name.as_str() {
+                "access_log" => Arc::new(AccessLog),
+                "rate_limit" => Arc::new(RateLimiter::new(config.rate_limits())),
+                "response_headers" => Arc::new(ResponseHeaders::new(
+                    config
+                        .response_headers
+                        .iter()
+                        .map(|(name, value)| {
+                            (
+                                name.parse().expect("Invalid response header name"),
+                                value.parse().expect("Invalid response header value"),
+                            )
+                        })
+                        .collect(),
+                )),
+                _ => panic!("Unknown middleware {name}"),
+            }
+        })
+        .collect()
+}
+
+/// Creates the connection handler serving the virtual hosts described by
+/// the configuration which are served by a listener restricted to the given
+/// host names, applying the shared middlewares
+///
+/// # Panics
+///
+/// The `build_connection_handler` function panics if a virtual host name is
+/// invalid, which is checked beforehand when validating the configuration.
+///
+fn build_connection_handler(
+    config: &Config,
+    listener_hosts: &[String],
+    middlewares: &MiddlewareChain,
+) -> ConnectionHandler {
+    let cgi_options = config.cgi_options();
+    let mut virtual_hosts = VirtualHosts::new(build_virtual_host(
+        &config.static_files.root,
+        &config.static_files.cgi_extensions,
+        &config.cgi,
+        &config.route,
+        config.error_pages(),
+        &cgi_options,
+    ));
+
+    for vhost in config.listener_virtual_hosts(listener_hosts) {
+        let patterns = vhost
+            .names
+            .iter()
+            .map(|name| HostPattern::parse(name).expect("Invalid virtual host name"))
+            .collect();
+
+        virtual_hosts.add(
+            patterns,
+            build_virtual_host(
+                &vhost.root,
+                &vhost.cgi_extensions,
+                &vhost.cgi,
+                &vhost.route,
+                vhost.error_pages(),
+                &cgi_options,
+            ),
+        );
+    }
+
+    ConnectionHandler::new(virtual_hosts, config.request_limits(), middlewares.clone())
+}
 
-const STATIC_FOLDER: &str = "public_html";
-const CGI_FOLDER: &str = "cgi-bin";
-const CGI_PATH: &str = "cgi-bin";
+/// Sets up the handlers of the SIGTERM and SIGINT signals, which raise the
+/// returned flag to request a graceful shutdown. Receiving one of them again
+/// while shutting down terminates the server immediately.
+///
+fn register_shutdown_signals() -> io::Result<Arc<AtomicBool>> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    for signal in [SIGTERM, SIGINT] {
+        signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&shutdown))?;
+        signal_hook::flag::register(signal, Arc::clone(&shutdown))?;
+    }
+
+    Ok(shutdown)
+}
+
+/// Starts a new server process from the server binary, which may have been
+/// replaced by a new build, handing the listening sockets over to it. The
+/// new process asks this one to shut down once it is ready to serve.
+///
+fn spawn_replacement(listener_fds: &[RawFd]) -> io::Result<Child> {
+    let mut args = env::args_os();
+    let program = args.next().unwrap_or_default();
+
+    let mut command = Command::new(program);
+    command.args(args);
+    listener::hand_over(&mut command, listener_fds);
+
+    command.spawn()
+}
+
+/// Reaps the new server processes which have exited, logging their exit
+/// status. They only exit while this process is running if they failed to
+/// start, e.g. because of an invalid configuration.
+///
+fn reap_replacements(replacements: &mut Vec<Child>) {
+    replacements.retain_mut(|child| match child.try_wait() {
+        Ok(None) => true,
+        Ok(Some(status)) => {
+            eprintln!("New server process {} exited: {status}", child.id());
+            false
+        }
+        Err(error) => {
+            eprintln!("Could not check new server process {}: {error}", child.id());
+            true
+        }
+    });
+}
+
+/// Starts a thread per listener accepting its connections, which are handled
+/// by the workers of the pool
+///
+fn start_acceptors(
+    listeners: Vec<ServedListener>,
+    pool: &Arc<ThreadPool>,
+    limiter: &Arc<ConnectionLimiter>,
+    shutdown: &Arc<ShutdownNotifier>,
+) -> Vec<JoinHandle<()>> {
+    listeners
+        .into_iter()
+        .map(|served| {
+            let pool = Arc::clone(pool);
+            let limiter = Arc::clone(limiter);
+            let shutdown = Arc::clone(shutdown);
+            thread::spawn(move || {
+                listener::serve(
+                    served.listener,
+                    served.conn_handler,
+                    pool,
+                    served.tls_config,
+                    limiter,
+                    shutdown,
+                )
+            })
+        })
+        .collect()
+}
+
+/// Starts the event loop serving every listener on a single thread, which
+/// only hands the requests to the workers of the pool once received
+///
+#[cfg(feature = "event-loop")]
+fn start_event_loop(
+    listeners: Vec<ServedListener>,
+    pool: &Arc<ThreadPool>,
+    limiter: &Arc<ConnectionLimiter>,
+    shutdown: &Arc<AtomicBool>,
+    shutdown_timeout: std::time::Duration,
+) -> Vec<JoinHandle<()>> {
+    let event_loop = EventLoop::new(listeners, Arc::clone(pool), Arc::clone(limiter))
+        .unwrap_or_else(|error| {
+            eprintln!("Could not start the event loop: {error}");
+            process::exit(1);
+        });
+    let shutdown = Arc::clone(shutdown);
+
+    vec![thread::spawn(move || {
+        event_loop.run(shutdown, shutdown_timeout)
+    })]
+}
 
 fn main() {
-    env_logger::init();
+    let cli = Cli::parse();
 
-    let listener = TcpListener::bind(ADDR_AND_PORT).unwrap();
-    let pool = ThreadPool::new(POOL_SIZE);
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(log_level) = cli.log_level {
+        logger.filter_level(log_level);
+    }
+    logger.init();
 
-    let conn_handler = Arc::new(ConnectionHandler::new(vec![
-        Box::new(CgiRequestHandler::new(
-            String::from(CGI_PATH),
-            String::from(CGI_FOLDER),
-            StaticRequestHandler::new(String::from(STATIC_FOLDER)),
-        )),
-        Box::new(StaticRequestHandler::new(String::from(STATIC_FOLDER))),
-    ]));
+    let config = load_config(&cli).unwrap_or_else(|error| {
+        eprintln!("Configuration error: {error}");
+        process::exit(1);
+    });
 
-    println!("Booting up.");
+    if cli.check_config {
+        println!("Configuration OK");
+        return;
+    }
+
+    let mut inherited =
+        InheritedListeners::from_env(env::var(listener::LISTEN_FDS_VAR).ok().as_deref());
+
+    let middlewares = build_middlewares(&config);
 
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
-        let handler_clone = Arc::clone(&conn_handler);
+    let mut listeners = vec![];
+    for listener_config in config.listeners() {
+        let listener = inherited
+            .take_tcp(listener_config.address)
+            .map_or_else(
+                || listener::bind(listener_config.address, listener_config.ipv6_only),
+                Ok,
+            )
+            .unwrap_or_else(|error| {
+                eprintln!("Could not listen on {}: {error}", listener_config.address);
+                process::exit(1);
+            });
+        let tls_config = listener_config.tls.as_ref().map(|tls_config| {
+            tls::load_server_config(tls_config).unwrap_or_else(|error| {
+                eprintln!(
+                    "Could not set up TLS on {}: {error}",
+                    listener_config.address
+                );
+                process::exit(1);
+            })
+        });
+        let conn_handler = Arc::new(build_connection_handler(
+            &config,
+            &listener_config.hosts,
+            &middlewares,
+        ));
+        listeners.push(ServedListener {
+            listener: Listener::Tcp(listener),
+            conn_handler,
+            tls_config,
+        });
+    }
 
-        pool.execute(move || {
-            (*handler_clone).handle_connection(stream);
+    for listener_config in &config.unix_listener {
+        let path = &listener_config.path;
+        let listener = inherited
+            .take_unix(path)
+            .map_or_else(|| listener::bind_unix(path, listener_config.mode), Ok)
+            .unwrap_or_else(|error| {
+                eprintln!("Could not listen on {}: {error}", path.display());
+                process::exit(1);
+            });
+        let conn_handler = Arc::new(build_connection_handler(
+            &config,
+            &listener_config.hosts,
+            &middlewares,
+        ));
+        listeners.push(ServedListener {
+            listener: Listener::Unix(listener, path.clone()),
+            conn_handler,
+            tls_config: None,
         });
     }
+    // Inherited sockets which are no longer configured are closed
+    drop(inherited);
+
+    // Only the event loop checks the flag, the listener threads are woken up
+    // by the shutdown notifier
+    #[cfg_attr(not(feature = "event-loop"), allow(unused_variables))]
+    let shutdown = register_shutdown_signals().unwrap_or_else(|error| {
+        eprintln!("Could not set up the signal handlers: {error}");
+        process::exit(1);
+    });
+    let shutdown_notifier = Arc::new(ShutdownNotifier::new().unwrap_or_else(|error| {
+        eprintln!("Could not set up the listeners: {error}");
+        process::exit(1);
+    }));
+    let mut signals =
+        Signals::new([SIGTERM, SIGINT, SIGHUP, SIGUSR2, SIGCHLD]).unwrap_or_else(|error| {
+            eprintln!("Could not set up the signal handlers: {error}");
+            process::exit(1);
+        });
+
+    let pool = Arc::new(ThreadPool::new(
+        config.server.pool_size,
+        config.server.max_pool_size,
+        config.server.queue_size,
+        config.worker_idle_timeout(),
+    ));
+    // Connections are counted across every listener
+    let limiter = Arc::new(ConnectionLimiter::new(
+        config.server.max_connections,
+        config.server.max_connections_per_ip,
+    ));
+
+    println!("Booting up.");
+
+    let listener_fds: Vec<RawFd> = listeners
+        .iter()
+        .map(|served| served.listener.as_raw_fd())
+        .collect();
+
+    #[cfg(feature = "event-loop")]
+    let acceptors = if config.server.event_loop {
+        start_event_loop(
+            listeners,
+            &pool,
+            &limiter,
+            &shutdown,
+            config.shutdown_timeout(),
+        )
+    } else {
+        start_acceptors(listeners, &pool, &limiter, &shutdown_notifier)
+    };
+    #[cfg(not(feature = "event-loop"))]
+    let acceptors = start_acceptors(listeners, &pool, &limiter, &shutdown_notifier);
+
+    // The sockets are served by this process now, so the previous one can
+    // stop accepting connections and complete its requests
+    if let Some(pid) =
+        listener::replaced_process(env::var(listener::REPLACED_PID_VAR).ok().as_deref())
+    {
+        // SAFETY: kill has no memory safety requirements
+        unsafe {
+            libc::kill(pid, SIGTERM);
+        }
+    }
+
+    // New server processes started with the listening sockets
+    let mut replacements = vec![];
+    for signal in signals.forever() {
+        if signal == SIGHUP || signal == SIGUSR2 {
+            match spawn_replacement(&listener_fds) {
+                Ok(child) => {
+                    println!("Started new server process {}.", child.id());
+                    replacements.push(child);
+                }
+                Err(error) => eprintln!("Could not start a new server process: {error}"),
+            }
+        } else if signal == SIGCHLD {
+            reap_replacements(&mut replacements);
+        } else {
+            break;
+        }
+    }
+    let shutdown_started = Instant::now();
+    reap_replacements(&mut replacements);
+    let handed_over = !replacements.is_empty();
+    shutdown_notifier.notify();
+
+    for acceptor in acceptors {
+        if acceptor.join().is_err() {
+            eprintln!("A listener thread panicked");
+        }
+    }
 
     println!("Shutting down.");
+
+    // Unix domain sockets handed over to a new server process are still in
+    // use, otherwise their files are left behind
+    if !handed_over {
+        for listener_config in &config.unix_listener {
+            if let Err(error) = fs::remove_file(&listener_config.path) {
+                eprintln!(
+                    "Could not remove {}: {error}",
+                    listener_config.path.display()
+                );
+            }
+        }
+    }
+
+    // The listener threads are done, so the pool is no longer shared
+    let pool = match Arc::try_unwrap(pool) {
+        Ok(pool) => pool,
+        Err(_) => {
+            eprintln!("The thread pool is still in use, exiting without waiting for the requests");
+            process::exit(1);
+        }
+    };
+    let remaining_time = config
+        .shutdown_timeout()
+        .saturating_sub(shutdown_started.elapsed());
+    let busy_workers = pool.shutdown(remaining_time);
+    if busy_workers > 0 {
+        let killed = cgi_handler::kill_running_programs();
+        eprintln!("{busy_workers} requests did not complete in time, killed {killed} CGI programs");
+    }
 }
@@ -1,42 +1,162 @@
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError, TrySendError},
+        Arc, Mutex, MutexGuard,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
+use log::{error, info};
+
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    workers: Mutex<Vec<Worker>>,
+    receiver: Arc<Mutex<mpsc::Receiver<Job>>>,
+    sender: Option<mpsc::SyncSender<Job>>,
+    state: Arc<PoolState>,
+    max_size: usize,
+    next_id: AtomicUsize,
+}
+
+type Job = Box<dyn Task>;
+
+/// A job executed by a worker, which can be given back to the caller when
+/// it couldn't be queued
+///
+trait Task: Send {
+    fn run(self: Box<Self>);
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+/// A function along with its input
+///
+struct QueuedJob<T, F> {
+    input: T,
+    f: F,
+}
+
+impl<T, F> Task for QueuedJob<T, F>
+where
+    T: Send + 'static,
+    F: FnOnce(T) + Send + 'static,
+{
+    fn run(self: Box<Self>) {
+        (self.f)(self.input)
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Counters shared between a pool and its workers
+///
+struct PoolState {
+    /// Number of running workers
+    size: AtomicUsize,
+    /// Number of workers executing a job
+    busy: AtomicUsize,
+    /// Number of jobs waiting for a worker
+    queued: AtomicUsize,
+    min_size: usize,
+    idle_timeout: Duration,
+}
+
+impl PoolState {
+    /// Decides whether an idle worker can stop, which is the case as long as
+    /// the pool is larger than its minimum size. The worker is removed from
+    /// the pool size if so.
+    fn retire_worker(&self) -> bool {
+        self.size
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |size| {
+                if size > self.min_size {
+                    Some(size - 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
 }
 
-type Job = Box<dyn FnOnce() + Send + 'static>;
+/// Snapshot of the activity of a thread pool
+///
+#[derive(Clone, Copy, Debug)]
+pub struct PoolMetrics {
+    /// Number of running workers
+    pub size: usize,
+    /// Number of workers executing a job
+    pub busy: usize,
+    /// Number of jobs waiting for a worker
+    pub queued: usize,
+}
 
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
-    /// The size is the number of threads in the pool.
+    /// The pool starts with `min_size` threads, and grows up to `max_size`
+    /// threads when jobs are submitted while every thread is busy. Threads
+    /// above the minimum stop after waiting for a job for `idle_timeout`.
+    /// The queue size is the number of jobs which can wait for a worker.
     ///
     /// # Panics
     ///
-    /// The `new` function will panic if the size is zero.
-    pub fn new(size: usize) -> ThreadPool {
-        assert!(size > 0);
+    /// The `new` function will panic if the minimum size or the queue size is
+    /// zero, or if the maximum size is lower than the minimum size.
+    pub fn new(
+        min_size: usize,
+        max_size: usize,
+        queue_size: usize,
+        idle_timeout: Duration,
+    ) -> ThreadPool {
+        assert!(min_size > 0);
+        assert!(max_size >= min_size);
+        assert!(queue_size > 0);
 
-        let (sender, receiver) = mpsc::channel();
+        let (sender, receiver) = mpsc::sync_channel(queue_size);
         let receiver = Arc::new(Mutex::new(receiver));
+        let state = Arc::new(PoolState {
+            size: AtomicUsize::new(min_size),
+            busy: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            min_size,
+            idle_timeout,
+        });
 
-        let mut workers = Vec::with_capacity(size);
+        let mut workers = Vec::with_capacity(max_size);
 
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+        for id in 0..min_size {
+            workers.push(Worker::new(id, Arc::clone(&receiver), Arc::clone(&state)));
         }
 
         ThreadPool {
-            workers,
+            workers: Mutex::new(workers),
+            receiver,
             sender: Some(sender),
+            state,
+            max_size,
+            next_id: AtomicUsize::new(min_size),
+        }
+    }
+
+    /// Returns the current size and activity of the pool
+    pub fn metrics(&self) -> PoolMetrics {
+        PoolMetrics {
+            size: self.state.size.load(Ordering::SeqCst),
+            busy: self.state.busy.load(Ordering::SeqCst),
+            queued: self.state.queued.load(Ordering::SeqCst),
         }
     }
 
-    /// Executes a function call in one of the threads in the pool.
+    /// Executes a function call in one of the threads in the pool. A thread
+    /// is added to the pool if no thread is available for the function and
+    /// the pool is below its maximum size. Blocks while the job queue is full.
     ///
     /// The f parameter is the function to be executed.
     ///
@@ -48,17 +168,141 @@ impl ThreadPool {
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
+        self.manage_workers();
+
+        let job = Box::new(QueuedJob {
+            input: (),
+            f: move |_| f(),
+        });
 
+        self.state.queued.fetch_add(1, Ordering::SeqCst);
         self.sender.as_ref().unwrap().send(job).unwrap();
     }
+
+    /// Executes a function call with the given input in one of the threads
+    /// in the pool like `execute`, unless the job queue is full. The input
+    /// is given back in this case instead of blocking, so that the caller
+    /// can still make use of it (e.g. to turn away a connection).
+    ///
+    /// # Panics
+    ///
+    /// The `try_execute` function will panic if the sender is None or the
+    /// receiver is dropped.
+    pub fn try_execute<T, F>(&self, input: T, f: F) -> Result<(), T>
+    where
+        T: Send + 'static,
+        F: FnOnce(T) + Send + 'static,
+    {
+        self.manage_workers();
+
+        let job: Job = Box::new(QueuedJob { input, f });
+
+        self.state.queued.fetch_add(1, Ordering::SeqCst);
+        match self.sender.as_ref().unwrap().try_send(job) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(job)) => {
+                self.state.queued.fetch_sub(1, Ordering::SeqCst);
+                let job = job
+                    .into_any()
+                    .downcast::<QueuedJob<T, F>>()
+                    .expect("Queued job has an unexpected type");
+                Err(job.input)
+            }
+            Err(TrySendError::Disconnected(_)) => panic!("The job receiver was dropped"),
+        }
+    }
+
+    /// Removes the workers which stopped after being idle, replaces the
+    /// workers whose thread stopped unexpectedly and grows the pool if every
+    /// worker is busy. Panics in jobs are caught by the workers, so workers
+    /// only stop unexpectedly if a worker itself failed.
+    fn manage_workers(&self) {
+        let mut workers = self.lock_workers();
+
+        workers.retain_mut(|worker| {
+            if !worker.thread.as_ref().is_some_and(|t| t.is_finished()) {
+                return true;
+            }
+
+            match worker.thread.take().unwrap().join() {
+                Ok(WorkerExit::Idle) => false,
+                _ => {
+                    error!("Worker {} stopped unexpectedly; respawning it.", worker.id);
+                    *worker = Worker::new(
+                        worker.id,
+                        Arc::clone(&self.receiver),
+                        Arc::clone(&self.state),
+                    );
+                    true
+                }
+            }
+        });
+
+        // Jobs being executed or waiting, including the one being submitted
+        let metrics = self.metrics();
+        let pending = metrics.busy + metrics.queued + 1;
+        if pending > metrics.size && metrics.size < self.max_size {
+            self.state.size.fetch_add(1, Ordering::SeqCst);
+            let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+            workers.push(Worker::new(
+                id,
+                Arc::clone(&self.receiver),
+                Arc::clone(&self.state),
+            ));
+            info!(
+                "Every worker is busy, pool grown to {} workers ({} queued jobs)",
+                metrics.size + 1,
+                metrics.queued
+            );
+        }
+    }
+
+    fn lock_workers(&self) -> MutexGuard<'_, Vec<Worker>> {
+        self.workers
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+
+    /// Stops accepting new jobs and waits for the workers to finish the jobs
+    /// already submitted, for at most the given timeout.
+    ///
+    /// Returns the number of workers still busy when the timeout expires.
+    /// These workers are detached instead of being waited for.
+    ///
+    /// # Panics
+    ///
+    /// The `shutdown` function will panic if a worker thread panicked.
+    pub fn shutdown(mut self, timeout: Duration) -> usize {
+        drop(self.sender.take());
+
+        let deadline = Instant::now() + timeout;
+        let mut workers: Vec<Worker> = self.lock_workers().drain(..).collect();
+        loop {
+            for worker in &mut workers {
+                if worker.thread.as_ref().is_some_and(|t| t.is_finished()) {
+                    println!("Shutting down worker {}", worker.id);
+                    worker.thread.take().unwrap().join().unwrap();
+                }
+            }
+
+            let busy = workers
+                .iter()
+                .filter(|worker| worker.thread.is_some())
+                .count();
+            if busy == 0 || Instant::now() >= deadline {
+                return busy;
+            }
+
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+    }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
         drop(self.sender.take());
 
-        for worker in &mut self.workers {
+        for worker in self.lock_workers().iter_mut() {
             println!("Shutting down worker {}", worker.id);
 
             if let Some(thread) = worker.thread.take() {
@@ -68,38 +312,79 @@ impl Drop for ThreadPool {
     }
 }
 
+/// Reason for which a worker stopped
+///
+enum WorkerExit {
+    /// The worker waited for a job for too long while the pool was above its
+    /// minimum size
+    Idle,
+    /// The pool was shut down
+    Disconnected,
+}
+
 struct Worker {
     id: usize,
-    thread: Option<thread::JoinHandle<()>>,
+    thread: Option<thread::JoinHandle<WorkerExit>>,
+}
+
+/// Returns the message a panic was started with, if it has one
+///
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown cause"
+    }
 }
 
 impl Worker {
     /// Creates a new worker with a given id and channel receiver. Received
-    /// functions are executed by the worker.
+    /// functions are executed by the worker, which keeps the counters of the
+    /// pool up to date. A function panicking doesn't stop the worker: the
+    /// panic is logged and the worker moves on to the next function.
     ///
     /// # Panics
     ///
     /// The returned Worker will panic if the mutex can no longer be acquired
     /// (e.g. another user of this mutex panicked while holding the mutex).
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let message = receiver
-                .lock()
-                .expect("Failed to acquire the job receiver mutex")
-                .recv();
-
-            match message {
-                Ok(job) => {
-                    println!("Worker {id} got a job; executing.");
-
-                    job();
-                }
-                Err(_) => {
-                    println!("Worker {id} disconnected; shutting down.");
-                    break;
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>, state: Arc<PoolState>) -> Worker {
+        let thread = thread::Builder::new()
+            .name(format!("worker-{id}"))
+            .spawn(move || loop {
+                let message = receiver
+                    .lock()
+                    .expect("Failed to acquire the job receiver mutex")
+                    .recv_timeout(state.idle_timeout);
+
+                match message {
+                    Ok(job) => {
+                        state.queued.fetch_sub(1, Ordering::SeqCst);
+                        state.busy.fetch_add(1, Ordering::SeqCst);
+                        println!("Worker {id} got a job; executing.");
+
+                        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| job.run())) {
+                            error!(
+                                "Worker {id} panicked while executing a job: {}",
+                                panic_message(payload.as_ref())
+                            );
+                        }
+                        state.busy.fetch_sub(1, Ordering::SeqCst);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        if state.retire_worker() {
+                            info!("Worker {id} idle; shutting down.");
+                            break WorkerExit::Idle;
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        println!("Worker {id} disconnected; shutting down.");
+                        break WorkerExit::Disconnected;
+                    }
                 }
-            }
-        });
+            })
+            .expect("Failed to spawn a worker thread");
 
         Worker {
             id,
@@ -107,3 +392,63 @@ impl Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDLE_TIMEOUT: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn panicking_jobs_do_not_shrink_the_pool() {
+        let pool = ThreadPool::new(1, 1, 4, IDLE_TIMEOUT);
+        let (sender, receiver) = mpsc::channel();
+
+        pool.execute(|| panic!("job failure"));
+        pool.execute(move || sender.send(()).unwrap());
+
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn pool_grows_when_busy_and_shrinks_when_idle() {
+        let pool = ThreadPool::new(1, 2, 4, IDLE_TIMEOUT);
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+        let (done_sender, done_receiver) = mpsc::channel();
+
+        pool.execute(move || {
+            let _ = release_receiver.recv();
+        });
+        pool.execute(move || done_sender.send(()).unwrap());
+
+        assert!(done_receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert_eq!(pool.metrics().size, 2);
+
+        drop(release_sender);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.metrics().size > 1 && Instant::now() < deadline {
+            thread::sleep(IDLE_TIMEOUT);
+        }
+        assert_eq!(pool.metrics().size, 1);
+    }
+
+    #[test]
+    fn jobs_are_given_back_when_the_queue_is_full() {
+        let pool = ThreadPool::new(1, 1, 1, IDLE_TIMEOUT);
+        let (started_sender, started_receiver) = mpsc::channel();
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            let _ = release_receiver.recv();
+        });
+        started_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap();
+
+        assert_eq!(pool.try_execute(1, drop), Ok(()));
+        assert_eq!(pool.try_execute(2, drop), Err(2));
+
+        drop(release_sender);
+    }
+}